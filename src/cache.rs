@@ -0,0 +1,615 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, rename};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use rt_result::RtResult;
+use config::{CacheCommand, Config};
+use dirs::rusty_tags_cache_dir;
+use types::{Source, TagsKind};
+use etags;
+use cleanup::TempFile;
+use std_lib::Toolchain;
+
+/// Identifies everything influencing the contents of cached tags, if
+/// any of it changes, then the cached tags have to be recreated. The options
+/// not applying to the kind of the tags or to the source are always 'false',
+/// so that changing them doesn't invalidate the cached tags.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug)]
+pub struct TagsKey {
+    /// the version of rusty-tags that created the tags
+    pub rusty_tags_version: String,
+
+    /// the ctags executable
+    pub ctags_exe: String,
+
+    /// the version output of the ctags executable
+    pub ctags_version: String,
+
+    /// options given to the ctags executable
+    pub ctags_options: String,
+
+    /// the kind of the tags, the file extension of the kind
+    pub kind: String,
+
+    /// if the tags contain the line numbers of the tags
+    pub line_numbers: bool,
+
+    /// if included emacs tags files are inlined
+    pub inline_includes: bool,
+
+    /// if only the tags of the source itself are cached
+    pub tags_list: bool,
+
+    /// if the files ignored by '.gitignore' files are excluded
+    pub gitignore: bool,
+
+    /// if the tags of the test code are excluded
+    pub exclude_test_code: bool,
+
+    /// the cfg options the '#[cfg]' predicates were evaluated
+    /// with, empty if they weren't evaluated
+    #[serde(default)]
+    pub target_cfg: Vec<String>
+}
+
+impl TagsKey {
+    /// A hash of the key, which is part of the names of the cached tags files,
+    /// so that the cached tags of different configurations are kept beside
+    /// of each other.
+    pub fn id(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish().to_string()
+    }
+}
+
+/// The meta data of a cached tags file, stored beside of it
+/// in a file with the additional extension '.json'.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheInfo {
+    /// the name of the crate - or for the standard library e.g. 'std-all' -
+    /// of the tags, the names of the cached tags files might be ambiguous
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// the key of the configuration the tags were created with
+    pub key: TagsKey,
+
+    /// the revision of the mutable source - a path or git dependency -
+    /// the tags were created from, see 'Source::revision'
+    #[serde(default)]
+    pub revision: Option<String>,
+
+    /// the directory of the source the tags were created from
+    #[serde(default)]
+    pub source_dir: Option<PathBuf>,
+
+    /// the tags file of the source, which contains - or for emacs
+    /// might include - the cached tags
+    #[serde(default)]
+    pub tags_file: Option<PathBuf>,
+
+    /// if the source is a workspace member
+    #[serde(default)]
+    pub is_root: bool,
+
+    /// the languages - beside of Rust - the tags were created for
+    #[serde(default)]
+    pub languages: Vec<String>,
+
+    /// the directories the tags were created for, see 'Source::src_dirs'
+    #[serde(default)]
+    pub src_dirs: Vec<PathBuf>,
+
+    /// the patterns of the files excluded from the tags
+    #[serde(default)]
+    pub exclude_paths: Vec<String>,
+
+    /// the enabled features the '#[cfg]' predicates were evaluated with
+    #[serde(default)]
+    pub features: Option<Vec<String>>,
+
+    /// the toolchain of the cached tags of the standard library
+    #[serde(default)]
+    pub toolchain: Option<Toolchain>,
+
+    /// seconds since the unix epoch when the cached tags
+    /// were last used by a run of rusty-tags
+    #[serde(default)]
+    pub last_used: u64
+}
+
+impl CacheInfo {
+    pub fn new(config: &Config, source: &Source, kind: TagsKind) -> RtResult<CacheInfo> {
+        let tags_files = source.tags_files(kind);
+        Ok(CacheInfo {
+            name: Some(source.name.clone()),
+            key: tags_files.key.clone(),
+            revision: source.revision(config)?,
+            source_dir: Some(source.dir.clone()),
+            tags_file: Some(tags_files.tags_file.clone()),
+            is_root: source.is_root,
+            languages: source.extra_languages.clone(),
+            src_dirs: source.src_dirs().iter().map(|d| d.to_path_buf()).collect(),
+            exclude_paths: source.exclude_paths.clone(),
+            features: source.features.clone(),
+            toolchain: None,
+            last_used: now()
+        })
+    }
+
+    /// The meta data of the cached tags `name` of the standard library
+    /// of `toolchain`, created from the sources at `src_dir`.
+    pub fn new_std_lib(name: &str, key: TagsKey, src_dir: &Path, toolchain: &Toolchain) -> CacheInfo {
+        CacheInfo {
+            name: Some(name.to_string()),
+            key,
+            revision: None,
+            source_dir: Some(src_dir.to_path_buf()),
+            tags_file: None,
+            is_root: false,
+            languages: Vec::new(),
+            src_dirs: Vec::new(),
+            exclude_paths: Vec::new(),
+            features: None,
+            toolchain: Some(toolchain.clone()),
+            last_used: now()
+        }
+    }
+
+    /// Loads the meta data of `cached_tags_file`. Returns `None` if there's
+    /// no meta data, or if it can't be read, e.g. because it was written
+    /// by an older rusty-tags version.
+    pub fn load(cached_tags_file: &Path) -> Option<CacheInfo> {
+        let mut file = File::open(info_file(cached_tags_file)).ok()?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Saves the meta data of `cached_tags_file`.
+    pub fn save(&self, cached_tags_file: &Path) -> RtResult<()> {
+        let info_file = info_file(cached_tags_file);
+        let info_dir = info_file.parent()
+            .ok_or(format!("Couldn't get directory of file '{}'", info_file.display()))?;
+
+        let mut tmp_file = TempFile::new_in(info_dir)?;
+        tmp_file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        rename(tmp_file.path(), &info_file)?;
+        Ok(())
+    }
+
+    /// Records that the cached tags `cached_tags_file` were used by this run. The
+    /// meta data is only rewritten if the last recorded usage is older than
+    /// `TOUCH_INTERVAL`, and failing to rewrite it doesn't fail the run.
+    pub fn touch(config: &Config, cached_tags_file: &Path) {
+        if let Some(mut info) = CacheInfo::load(cached_tags_file) {
+            let now = now();
+            if now.saturating_sub(info.last_used) < TOUCH_INTERVAL {
+                return;
+            }
+
+            info.last_used = now;
+            if let Err(err) = info.save(cached_tags_file) {
+                verbose!(config, "Couldn't record the usage of the cached tags '{}': {}", cached_tags_file.display(), err);
+            }
+        }
+    }
+}
+
+/// seconds after which the usage of cached tags is recorded again, so
+/// the recorded usage might be up to this interval older than the last one
+const TOUCH_INTERVAL: u64 = 60 * 60;
+
+/// the file containing the meta data of `cached_tags_file`
+pub fn info_file(cached_tags_file: &Path) -> PathBuf {
+    let mut file_name = cached_tags_file.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".json");
+    cached_tags_file.with_file_name(file_name)
+}
+
+/// Executes the cache subcommand `cmd`.
+pub fn execute(config: &Config, cmd: &CacheCommand) -> RtResult<()> {
+    let cache_dir = rusty_tags_cache_dir()?;
+    let entries = cache_entries(cache_dir)?;
+    match *cmd {
+        CacheCommand::Stats => print_stats(cache_dir, &entries),
+
+        CacheCommand::Prune => {
+            let mut removable: FnvHashSet<usize> = entries.iter().enumerate()
+                .filter(|&(_, e)| {
+                    e.info.as_ref()
+                        .and_then(|i| i.source_dir.as_ref())
+                        .map(|d| ! d.is_dir())
+                        .unwrap_or(false)
+                })
+                .map(|(i, _)| i)
+                .collect();
+
+            removable.extend(uninstalled_toolchain_entries(&entries));
+
+            remove_entries(config, &entries, removable)?;
+            remove_orphaned_info_files(config, cache_dir)
+        }
+
+        CacheCommand::Gc { max_size, older_than } => {
+            let mut removable = FnvHashSet::default();
+            if let Some(older_than) = older_than {
+                // the usage is only recorded once per 'TOUCH_INTERVAL', the duration is
+                // extended by the interval to keep the entries used inside of the duration
+                let min_last_used = now().saturating_sub(older_than.as_secs()).saturating_sub(TOUCH_INTERVAL);
+                removable.extend(entries.iter().enumerate()
+                    .filter(|&(_, e)| e.last_used < min_last_used)
+                    .map(|(i, _)| i));
+            }
+
+            if let Some(max_size) = max_size {
+                // drop the least recently used entries until the max size is reached
+                let mut by_last_used: Vec<_> = (0..entries.len()).collect();
+                by_last_used.sort_by_key(|i| entries[*i].last_used);
+
+                let mut size: u64 = entries.iter().enumerate()
+                    .filter(|&(i, _)| ! removable.contains(&i))
+                    .map(|(_, e)| e.size)
+                    .sum();
+
+                for i in by_last_used {
+                    if size <= max_size {
+                        break;
+                    }
+
+                    if removable.insert(i) {
+                        size -= entries[i].size;
+                    }
+                }
+            }
+
+            remove_entries(config, &entries, removable)
+        }
+    }
+}
+
+/// Removes the cached tags of the standard library of toolchains which aren't installed anymore.
+pub fn prune_std_lib_tags(config: &Config) -> RtResult<()> {
+    let entries = cache_entries(rusty_tags_cache_dir()?)?;
+    let removable = uninstalled_toolchain_entries(&entries);
+    if removable.is_empty() {
+        return Ok(());
+    }
+
+    remove_entries(config, &entries, removable)
+}
+
+/// The cached tags of the standard library of toolchains which aren't installed anymore.
+fn uninstalled_toolchain_entries(entries: &[CacheEntry]) -> FnvHashSet<usize> {
+    let mut is_installed: FnvHashMap<&Toolchain, bool> = FnvHashMap::default();
+    let mut uninstalled = FnvHashSet::default();
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(toolchain) = entry.info.as_ref().and_then(|i| i.toolchain.as_ref()) {
+            if ! *is_installed.entry(toolchain).or_insert_with(|| toolchain.is_installed()) {
+                uninstalled.insert(i);
+            }
+        }
+    }
+
+    uninstalled
+}
+
+/// A cached tags file and its meta data.
+struct CacheEntry {
+    /// the cached tags file
+    cached_tags_file: PathBuf,
+
+    /// the name of the crate of the cached tags
+    name: String,
+
+    /// the kind of the cached tags
+    kind: TagsKind,
+
+    /// the size of the cached tags file and of its meta data
+    size: u64,
+
+    /// the meta data, 'None' for cached tags of older rusty-tags versions
+    info: Option<CacheInfo>,
+
+    /// seconds since the unix epoch when the cached tags were last used,
+    /// the modification time of the cached tags if there's no meta data
+    last_used: u64
+}
+
+fn cache_entries(cache_dir: &Path) -> RtResult<Vec<CacheEntry>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(cache_dir)? {
+        let path = dir_entry?.path();
+        let kind = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext == TagsKind::Vi.file_extension()    => TagsKind::Vi,
+            Some(ext) if ext == TagsKind::Emacs.file_extension() => TagsKind::Emacs,
+            _ => continue
+        };
+
+        let metadata = fs::metadata(&path)?;
+        if ! metadata.is_file() {
+            continue;
+        }
+
+        // cached tags with meta data are named '{name}-{hash}-{key id}.{ext}', the
+        // ones of older rusty-tags versions without meta data '{name}-{hash}.{ext}'
+        let info = CacheInfo::load(&path);
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let name = match info {
+            Some(CacheInfo { name: Some(ref name), .. }) => Some(name.as_str()),
+            Some(_) => stem.rsplitn(3, '-').nth(2),
+            None    => stem.rsplit_once('-').map(|(name, _)| name)
+        };
+
+        let name = match name {
+            Some(name) => name.to_string(),
+            None       => continue
+        };
+
+        let info_size = fs::metadata(info_file(&path)).map(|m| m.len()).unwrap_or(0);
+        let last_used = match info {
+            Some(ref info) if info.last_used > 0 => info.last_used,
+            _ => metadata.modified().map(secs_since_epoch).unwrap_or(0)
+        };
+
+        entries.push(CacheEntry {
+            cached_tags_file: path,
+            name,
+            kind,
+            size: metadata.len() + info_size,
+            info,
+            last_used
+        });
+    }
+
+    Ok(entries)
+}
+
+fn print_stats(cache_dir: &Path, entries: &[CacheEntry]) -> RtResult<()> {
+    let mut crates: FnvHashMap<&str, (usize, u64)> = FnvHashMap::default();
+    for entry in entries {
+        let stats = crates.entry(&entry.name).or_insert((0, 0));
+        stats.0 += 1;
+        stats.1 += entry.size;
+    }
+
+    let mut crates: Vec<_> = crates.into_iter().collect();
+    crates.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(b.0)));
+
+    let total_size: u64 = entries.iter().map(|e| e.size).sum();
+    let without_info = entries.iter().filter(|e| e.info.is_none()).count();
+
+    println!("Cache directory: {}", cache_dir.display());
+    println!("Entries: {}, crates: {}, size: {}", entries.len(), crates.len(), format_size(total_size));
+    if without_info > 0 {
+        println!("Entries without meta data of older rusty-tags versions: {}", without_info);
+    }
+
+    if ! crates.is_empty() {
+        let name_width = crates.iter().map(|c| c.0.len()).max().unwrap_or(0);
+        println!();
+        println!("{:<width$}  {:>7}  {:>10}", "crate", "entries", "size", width = name_width);
+        for (name, (num_entries, size)) in crates {
+            println!("{:<width$}  {:>7}  {:>10}", name, num_entries, format_size(size), width = name_width);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the `removable` entries, besides the ones still included by the emacs tags files
+/// of other entries, removing them would break these tags files. The emacs tags files of
+/// removed entries of dependencies are removed too, because they might include removed entries,
+/// unless they're also the tags files of kept entries.
+fn remove_entries(config: &Config, entries: &[CacheEntry], mut removable: FnvHashSet<usize>) -> RtResult<()> {
+    // The emacs entries of roots are the only record of the tags files of the roots,
+    // which include other entries, so they're kept as long as these tags files exist.
+    let num_removable = removable.len();
+    removable.retain(|i| ! is_root_with_emacs_tags(&entries[*i]));
+    let mut num_kept = num_removable - removable.len();
+
+    // keeping an entry might keep further entries it includes
+    loop {
+        let included = included_entries(entries, &removable);
+        let num_removable = removable.len();
+        removable.retain(|i| ! included.contains(&entries[*i].cached_tags_file));
+        num_kept += num_removable - removable.len();
+        if removable.len() == num_removable {
+            break;
+        }
+    }
+
+    // the entries of different configurations of a source share its tags file
+    let kept_tags_files: FnvHashSet<&Path> = entries.iter().enumerate()
+        .filter(|&(i, _)| ! removable.contains(&i))
+        .filter_map(|(_, e)| e.info.as_ref().and_then(|i| i.tags_file.as_deref()))
+        .collect();
+
+    let mut removed_size = 0;
+    for i in &removable {
+        let entry = &entries[*i];
+        verbose!(config, "Removing cached tags '{}'", entry.cached_tags_file.display());
+        fs::remove_file(&entry.cached_tags_file)?;
+
+        let info_file = info_file(&entry.cached_tags_file);
+        if info_file.is_file() {
+            fs::remove_file(&info_file)?;
+        }
+
+        if let Some(CacheInfo { tags_file: Some(ref tags_file), is_root: false, .. }) = entry.info {
+            if entry.kind == TagsKind::Emacs && tags_file.is_file() && ! kept_tags_files.contains(tags_file.as_path()) {
+                verbose!(config, "Removing tags file '{}'", tags_file.display());
+                fs::remove_file(tags_file)?;
+            }
+        }
+
+        removed_size += entry.size;
+    }
+
+    info!(config, "Removed {} cache entries with a size of {}", removable.len(), format_size(removed_size));
+    if num_kept > 0 {
+        info!(config, "Kept {} cache entries needed by existing emacs tags files", num_kept);
+    }
+
+    Ok(())
+}
+
+fn is_root_with_emacs_tags(entry: &CacheEntry) -> bool {
+    match entry.info {
+        Some(CacheInfo { tags_file: Some(ref tags_file), is_root: true, .. }) => {
+            entry.kind == TagsKind::Emacs && tags_file.is_file()
+        }
+
+        _ => false
+    }
+}
+
+/// The cached tags files included - directly or indirectly - by the emacs tags files
+/// of roots or of entries that aren't `removable`, and by the kept entries themselves.
+fn included_entries(entries: &[CacheEntry], removable: &FnvHashSet<usize>) -> FnvHashSet<PathBuf> {
+    let mut to_visit = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.kind != TagsKind::Emacs {
+            continue;
+        }
+
+        let is_root = entry.info.as_ref().map(|i| i.is_root).unwrap_or(false);
+        if ! removable.contains(&i) {
+            to_visit.push(entry.cached_tags_file.clone());
+        }
+
+        if is_root || ! removable.contains(&i) {
+            if let Some(tags_file) = entry.info.as_ref().and_then(|i| i.tags_file.clone()) {
+                to_visit.push(tags_file);
+            }
+        }
+    }
+
+    let mut visited = FnvHashSet::default();
+    let mut included = FnvHashSet::default();
+    while let Some(tags_file) = to_visit.pop() {
+        if ! visited.insert(tags_file.clone()) {
+            continue;
+        }
+
+        for file in etags::included_files(&tags_file).unwrap_or_default() {
+            included.insert(file.clone());
+            to_visit.push(file);
+        }
+    }
+
+    included
+}
+
+/// Removes meta data files whose cached tags file doesn't exist anymore.
+fn remove_orphaned_info_files(config: &Config, cache_dir: &Path) -> RtResult<()> {
+    for dir_entry in fs::read_dir(cache_dir)? {
+        let path = dir_entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        if ! path.with_extension("").is_file() {
+            verbose!(config, "Removing orphaned meta data '{}'", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a size like '500M', '2G', '100KB' or '1024'.
+pub fn parse_size(size_str: &str) -> RtResult<u64> {
+    let size_str = size_str.trim();
+    let upper = size_str.to_uppercase();
+    let number_str = upper.trim_end_matches(|c: char| c.is_alphabetic());
+    let unit = &upper[number_str.len()..];
+    let factor: u64 = match unit {
+        "" | "B"           => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size '{}', expected e.g. 500M or 2G", size_str).into())
+    };
+
+    let number = number_str.trim().parse::<f64>()
+        .map_err(|_| format!("Invalid size '{}', expected e.g. 500M or 2G", size_str))?;
+
+    Ok((number * factor as f64) as u64)
+}
+
+/// Parses a duration like '30d', '12h', '2w', '45m' or '90s'.
+pub fn parse_duration(duration_str: &str) -> RtResult<Duration> {
+    let duration_str = duration_str.trim();
+    let number_str = duration_str.trim_end_matches(|c: char| c.is_alphabetic());
+    let factor: u64 = match &duration_str[number_str.len()..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration '{}', expected e.g. 30d, 12h or 2w", duration_str).into())
+    };
+
+    let secs = number_str.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(|| format!("Invalid duration '{}', expected e.g. 30d, 12h or 2w", duration_str))?;
+
+    Ok(Duration::from_secs(secs))
+}
+
+fn format_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn now() -> u64 {
+    secs_since_epoch(SystemTime::now())
+}
+
+fn secs_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("100KB").unwrap(), 100 * 1024);
+        assert_eq!(parse_size("500M").unwrap(), 500 * 1024 * 1024);
+        assert_eq!(parse_size("1.5g").unwrap(), 3 * 512 * 1024 * 1024);
+        assert!(parse_size("500X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::from_secs(12 * 60 * 60));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::from_secs(14 * 24 * 60 * 60));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+    }
+}
@@ -0,0 +1,936 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use fnv::FnvHashMap;
+
+use rt_result::RtResult;
+use config::Config;
+use types::{Source, TagsKind};
+use etags;
+use cleanup;
+
+/// The cfg options which are always known, even if they aren't set for the target.
+const BUILTIN_CFG_OPTIONS: [&str; 6] = ["unix", "windows", "debug_assertions", "miri", "doc", "doctest"];
+
+/// The cfg options of the compilation target, printed by 'rustc --print cfg'.
+#[derive(Debug)]
+pub struct TargetCfg {
+    /// the set options, e.g. 'unix' or 'target_os="linux"'
+    options: Vec<(String, Option<String>)>
+}
+
+impl TargetCfg {
+    /// Gets the cfg options of `target` - or of the host - from the
+    /// rustc of `$RUSTC` or of the toolchain used at `start_dir`.
+    pub fn detect(start_dir: &Path, target: Option<&str>) -> RtResult<TargetCfg> {
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let mut cmd = Command::new(&rustc);
+        cmd.arg("--print").arg("cfg").current_dir(start_dir);
+        if let Some(target) = target {
+            cmd.arg("--target").arg(target);
+        }
+
+        let output = cleanup::run(&mut cmd)
+            .map_err(|err| format!("'rustc' execution failed: {}\nIs 'rustc' correctly installed?", err))?;
+
+        if ! output.status.success() {
+            return Err(format!("Couldn't get the cfg options of the target '{}' by 'rustc --print cfg': {}",
+                               target.unwrap_or("host"), String::from_utf8_lossy(&output.stderr).trim()).into());
+        }
+
+        let options = String::from_utf8_lossy(&output.stdout).lines()
+            .map(str::trim)
+            .filter(|l| ! l.is_empty())
+            .map(|l| match l.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.trim_matches('"').to_string())),
+                None                => (l.to_string(), None)
+            })
+            .collect();
+
+        Ok(TargetCfg { options })
+    }
+
+    /// The options as printed by rustc, e.g. 'target_os="linux"'.
+    pub fn option_strings(&self) -> Vec<String> {
+        self.options.iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("{}=\"{}\"", name, value),
+                None        => name.clone()
+            })
+            .collect()
+    }
+
+    /// If the option `name` - with `value` - is set, 'None' if `name` is unknown,
+    /// e.g. because it's set by a build script, so it can't be evaluated.
+    fn value(&self, name: &str, value: Option<&str>) -> Option<bool> {
+        let is_known = BUILTIN_CFG_OPTIONS.contains(&name) || self.options.iter().any(|(n, _)| n == name);
+        if ! is_known {
+            return None;
+        }
+
+        Some(self.options.iter().any(|(n, v)| n == name && v.as_deref() == value))
+    }
+}
+
+/// Which code is excluded from the tags of a source. The items are
+/// found by scanning the Rust sources for their attributes, and the
+/// tags inside of the lines of excluded items are removed.
+pub struct CodeFilter<'a> {
+    /// exclude '#[cfg(test)]' items and '#[test]' functions
+    exclude_tests: bool,
+
+    /// exclude the items whose '#[cfg]' is false for the target
+    target_cfg: Option<&'a TargetCfg>,
+
+    /// the enabled features of the source, 'None' if they're unknown
+    features: Option<&'a [String]>,
+
+    /// if the source is a procedural macro crate - which sets the
+    /// cfg option 'proc_macro' - 'None' if it's unknown
+    is_proc_macro: Option<bool>
+}
+
+impl<'a> CodeFilter<'a> {
+    /// The filter of the code of `source`, 'None' if no code is excluded.
+    pub fn for_source(config: &'a Config, source: &'a Source) -> Option<CodeFilter<'a>> {
+        let exclude_tests = if source.is_root {
+            config.exclude_root_test_code
+        } else {
+            config.exclude_test_code
+        };
+
+        CodeFilter::new(exclude_tests, config.target_cfg.as_ref(), source.features.as_deref(), Some(source.is_proc_macro))
+    }
+
+    /// The filter of the code of the standard library, whose features are unknown.
+    pub fn for_std_lib(config: &'a Config) -> Option<CodeFilter<'a>> {
+        CodeFilter::new(config.exclude_test_code, config.target_cfg.as_ref(), None, None)
+    }
+
+    fn new(exclude_tests: bool, target_cfg: Option<&'a TargetCfg>, features: Option<&'a [String]>, is_proc_macro: Option<bool>) -> Option<CodeFilter<'a>> {
+        if exclude_tests || target_cfg.is_some() {
+            Some(CodeFilter { exclude_tests, target_cfg, features, is_proc_macro })
+        } else {
+            None
+        }
+    }
+
+    /// Removes the tags of the excluded code from `tags_file` of `kind`.
+    pub fn filter_tags(&self, kind: TagsKind, tags_file: &Path) -> RtResult<()> {
+        let source_files = match kind {
+            TagsKind::Vi    => vi_source_files(tags_file)?,
+            TagsKind::Emacs => etags::source_files(tags_file)?
+        };
+
+        let excluded = self.excluded_code(&source_files);
+        match kind {
+            TagsKind::Vi    => filter_vi_tags(tags_file, &excluded),
+            TagsKind::Emacs => etags::filter(tags_file, |file, line| ! excluded.contains(file, line))
+        }
+    }
+
+    fn excluded_code(&self, source_files: &[PathBuf]) -> ExcludedCode {
+        let mut excluded = ExcludedCode::default();
+        for file in source_files.iter().filter(|f| f.extension().map(|e| e == "rs").unwrap_or(false)) {
+            let contents = match fs::read_to_string(file) {
+                Ok(contents) => contents,
+                Err(_)       => continue
+            };
+
+            let file_code = self.scan(file, &tokenize(&contents));
+            excluded.module_paths.extend(file_code.module_paths);
+            excluded.files.insert(file.clone(), file_code.lines);
+        }
+
+        excluded
+    }
+
+    /// Scans the `tokens` of the source `file` for excluded items.
+    fn scan(&self, file: &Path, tokens: &[Token]) -> FileCode {
+        let mut code = FileCode::default();
+
+        // the names of the enclosing inline modules with the brace depth of their bodies
+        let mut inline_modules: Vec<(&str, usize)> = Vec::new();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < tokens.len() {
+            // an inner attribute of the file, e.g. '#![cfg(test)]'
+            if is_punct(tokens, i, '#') && is_punct(tokens, i + 1, '!') && is_punct(tokens, i + 2, '[') {
+                let end = matching_bracket(tokens, i + 2);
+                if depth == 0 && self.excludes_attr(tokens.get(i + 3..end).unwrap_or(&[])) {
+                    code.lines = vec![(1, usize::MAX)];
+                    return code;
+                }
+
+                i = end + 1;
+                continue;
+            }
+
+            if is_punct(tokens, i, '#') && is_punct(tokens, i + 1, '[') {
+                let start_line = tokens[i].line;
+                let mut excluded = false;
+                let mut path_attr = None;
+                let mut j = i;
+                while is_punct(tokens, j, '#') && is_punct(tokens, j + 1, '[') {
+                    let end = matching_bracket(tokens, j + 1);
+                    let attr = tokens.get(j + 2..end).unwrap_or(&[]);
+                    excluded = excluded || self.excludes_attr(attr);
+                    if let (Some(TokenKind::Ident("path")), Some(TokenKind::Str(path))) =
+                        (attr.first().map(|t| &t.kind), attr.get(2).map(|t| &t.kind)) {
+                        path_attr = Some(*path);
+                    }
+
+                    j = end + 1;
+                }
+
+                if ! excluded {
+                    i = j;
+                    continue;
+                }
+
+                if j >= tokens.len() {
+                    break;
+                }
+
+                let end = item_end(tokens, j);
+                code.lines.push((start_line, tokens.get(end).map(|t| t.line).unwrap_or(usize::MAX)));
+                if let Some(name) = file_module(&tokens[j..=end]) {
+                    let modules: Vec<&str> = inline_modules.iter().map(|(name, _)| *name).collect();
+                    code.module_paths.extend(module_paths(file, &modules, name, path_attr));
+                }
+
+                i = end + 1;
+                continue;
+            }
+
+            match tokens[i].kind {
+                TokenKind::Ident("mod") => {
+                    if let (Some(TokenKind::Ident(name)), true) = (tokens.get(i + 1).map(|t| &t.kind), is_punct(tokens, i + 2, '{')) {
+                        inline_modules.push((name, depth + 1));
+                    }
+                }
+
+                TokenKind::Punct('{') => depth += 1,
+
+                TokenKind::Punct('}') => {
+                    if inline_modules.last().map(|(_, d)| *d == depth).unwrap_or(false) {
+                        inline_modules.pop();
+                    }
+
+                    depth = depth.saturating_sub(1);
+                }
+
+                _ => {}
+            }
+
+            i += 1;
+        }
+
+        code
+    }
+
+    /// If the item with the attribute `attr` - the tokens inside of '#[...]' - is excluded.
+    fn excludes_attr(&self, attr: &[Token]) -> bool {
+        // the path of the attribute, e.g. 'test' or 'tokio::test'
+        let path: Vec<&str> = attr.iter()
+            .take_while(|t| matches!(t.kind, TokenKind::Ident(_) | TokenKind::Punct(':')))
+            .filter_map(|t| match t.kind { TokenKind::Ident(ident) => Some(ident), _ => None })
+            .collect();
+
+        match path.as_slice() {
+            [.., "test"] if self.exclude_tests => true,
+            ["cfg"] if is_punct(attr, 1, '(') => {
+                let mut parser = CfgParser { tokens: &attr[2..], pos: 0 };
+                parser.parse().and_then(|cfg| cfg.eval(self)) == Some(false)
+            }
+
+            _ => false
+        }
+    }
+
+    /// The value of the cfg option `name` - with the `value` of e.g. 'feature = "std"' -
+    /// 'None' if it's unknown, then the predicates depending on it aren't evaluated.
+    fn cfg_value(&self, name: &str, value: Option<&str>) -> Option<bool> {
+        match (name, value) {
+            ("test", None) if self.exclude_tests => Some(false),
+            ("test", _)                          => None,
+            ("feature", Some(feature))           => self.features.map(|fs| fs.iter().any(|f| f == feature)),
+            ("proc_macro", None)                 => self.is_proc_macro,
+            _                                    => self.target_cfg.and_then(|cfg| cfg.value(name, value))
+        }
+    }
+}
+
+/// The excluded code of the source files of a tags file.
+#[derive(Default)]
+struct ExcludedCode {
+    /// the excluded lines of the source files, as ranges of first and last line
+    files: FnvHashMap<PathBuf, Vec<(usize, usize)>>,
+
+    /// the files and directories of excluded modules, e.g. of '#[cfg(test)] mod tests;'
+    module_paths: Vec<PathBuf>
+}
+
+impl ExcludedCode {
+    fn contains(&self, file: &Path, line: usize) -> bool {
+        if self.module_paths.iter().any(|p| file.starts_with(p)) {
+            return true;
+        }
+
+        self.files.get(file)
+            .map(|lines| lines.iter().any(|(first, last)| line >= *first && line <= *last))
+            .unwrap_or(false)
+    }
+}
+
+/// If an inner attribute of the Rust `source` of a crate root enables the unstable
+/// `feature` - by '#![feature(...)]' or by '#![cfg_attr(..., feature(...))]'.
+pub fn enables_feature(source: &str, feature: &str) -> bool {
+    let tokens = tokenize(source);
+    let mut i = 0;
+    while i < tokens.len() {
+        if ! (is_punct(&tokens, i, '#') && is_punct(&tokens, i + 1, '!') && is_punct(&tokens, i + 2, '[')) {
+            i += 1;
+            continue;
+        }
+
+        // the feature might be enabled conditionally by 'cfg_attr'
+        let end = matching_bracket(&tokens, i + 2);
+        for j in i + 3..end {
+            if tokens[j].kind == TokenKind::Ident("feature") && is_punct(&tokens, j + 1, '(') {
+                let features = tokens.get(j + 2..matching_bracket(&tokens, j + 1)).unwrap_or(&[]);
+                if features.iter().any(|t| t.kind == TokenKind::Ident(feature)) {
+                    return true;
+                }
+            }
+        }
+
+        i = end + 1;
+    }
+
+    false
+}
+
+/// The excluded code of one source file.
+#[derive(Default)]
+struct FileCode {
+    lines: Vec<(usize, usize)>,
+    module_paths: Vec<PathBuf>
+}
+
+/// The possible paths of the file module `name` declared inside of the inline
+/// `modules` of `file`, and the directory of its sub modules.
+fn module_paths(file: &Path, modules: &[&str], name: &str, path_attr: Option<&str>) -> Vec<PathBuf> {
+    let file_dir = file.parent().unwrap_or_else(|| Path::new(""));
+    if let Some(path) = path_attr {
+        return vec![file_dir.join(path)];
+    }
+
+    // the modules of 'lib.rs', 'main.rs' and 'mod.rs' are beside of
+    // them, the ones of e.g. 'foo.rs' are in the directory 'foo'
+    let mut dir = match file.file_stem().and_then(|s| s.to_str()) {
+        Some("lib") | Some("main") | Some("mod") | None => file_dir.to_path_buf(),
+        Some(stem)                                      => file_dir.join(stem)
+    };
+
+    for module in modules {
+        dir.push(module);
+    }
+
+    vec![dir.join(format!("{}.rs", name)), dir.join(name)]
+}
+
+/// The name of the module if `item` is a file module, e.g. 'pub mod tests;'.
+fn file_module<'a>(item: &[Token<'a>]) -> Option<&'a str> {
+    let mod_pos = item.iter().position(|t| t.kind == TokenKind::Ident("mod"))?;
+    match (item.get(mod_pos + 1).map(|t| &t.kind), item.get(mod_pos + 2).map(|t| &t.kind)) {
+        (Some(TokenKind::Ident(name)), Some(TokenKind::Punct(';'))) => Some(name),
+        _ => None
+    }
+}
+
+/// The index of the last token of the item starting at `start`, which ends
+/// with its first block, a ';' or a ',' - e.g. of an enum variant - or before
+/// the closing bracket of the enclosing item.
+fn item_end(tokens: &[Token], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i < tokens.len() {
+        match tokens[i].kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') => depth += 1,
+            TokenKind::Punct('{') if depth == 0 => return matching_bracket(tokens, i),
+            TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => {
+                if depth == 0 {
+                    return i.saturating_sub(1).max(start);
+                }
+
+                depth -= 1;
+            }
+
+            TokenKind::Punct(';') | TokenKind::Punct(',') if depth == 0 => return i,
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    tokens.len().saturating_sub(1)
+}
+
+/// The index of the bracket closing the one at `open`, or
+/// the index of the last token if it isn't closed.
+fn matching_bracket(tokens: &[Token], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        match token.kind {
+            TokenKind::Punct('(') | TokenKind::Punct('[') | TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct(')') | TokenKind::Punct(']') | TokenKind::Punct('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    tokens.len().saturating_sub(1)
+}
+
+fn is_punct(tokens: &[Token], i: usize, c: char) -> bool {
+    tokens.get(i).map(|t| t.kind == TokenKind::Punct(c)).unwrap_or(false)
+}
+
+/// A 'cfg' predicate, e.g. 'all(unix, feature = "std")'.
+enum Cfg<'a> {
+    Option(&'a str, Option<&'a str>),
+    All(Vec<Cfg<'a>>),
+    Any(Vec<Cfg<'a>>),
+    Not(Box<Cfg<'a>>)
+}
+
+impl<'a> Cfg<'a> {
+    /// Evaluates the predicate, 'None' if it depends on unknown options.
+    fn eval(&self, filter: &CodeFilter) -> Option<bool> {
+        match *self {
+            Cfg::Option(name, value) => filter.cfg_value(name, value),
+
+            Cfg::All(ref cfgs) => {
+                let values: Vec<_> = cfgs.iter().map(|c| c.eval(filter)).collect();
+                if values.contains(&Some(false)) {
+                    Some(false)
+                } else if values.iter().all(|v| *v == Some(true)) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+
+            Cfg::Any(ref cfgs) => {
+                let values: Vec<_> = cfgs.iter().map(|c| c.eval(filter)).collect();
+                if values.contains(&Some(true)) {
+                    Some(true)
+                } else if values.iter().all(|v| *v == Some(false)) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+
+            Cfg::Not(ref cfg) => cfg.eval(filter).map(|v| ! v)
+        }
+    }
+}
+
+/// Parses a 'cfg' predicate from the tokens inside of 'cfg(...)'.
+struct CfgParser<'t, 'a: 't> {
+    tokens: &'t [Token<'a>],
+    pos: usize
+}
+
+impl<'t, 'a> CfgParser<'t, 'a> {
+    fn parse(&mut self) -> Option<Cfg<'a>> {
+        let name = match self.next()? {
+            TokenKind::Ident(name) => name,
+            _                      => return None
+        };
+
+        match self.peek() {
+            Some(TokenKind::Punct('(')) => {
+                self.pos += 1;
+                let mut cfgs = Vec::new();
+                while self.peek() != Some(TokenKind::Punct(')')) {
+                    cfgs.push(self.parse()?);
+                    if self.peek() == Some(TokenKind::Punct(',')) {
+                        self.pos += 1;
+                    }
+                }
+
+                self.pos += 1;
+                match name {
+                    "all"                  => Some(Cfg::All(cfgs)),
+                    "any"                  => Some(Cfg::Any(cfgs)),
+                    "not" if cfgs.len() == 1 => cfgs.pop().map(|c| Cfg::Not(Box::new(c))),
+                    _                      => None
+                }
+            }
+
+            Some(TokenKind::Punct('=')) => {
+                self.pos += 1;
+                match self.next()? {
+                    TokenKind::Str(value) => Some(Cfg::Option(name, Some(value))),
+                    _                     => None
+                }
+            }
+
+            _ => Some(Cfg::Option(name, None))
+        }
+    }
+
+    fn peek(&self) -> Option<TokenKind<'a>> {
+        self.tokens.get(self.pos).map(|t| t.kind)
+    }
+
+    fn next(&mut self) -> Option<TokenKind<'a>> {
+        let kind = self.peek();
+        self.pos += 1;
+        kind
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+enum TokenKind<'a> {
+    Ident(&'a str),
+    /// the contents of a string literal, without its quotes
+    Str(&'a str),
+    Punct(char),
+    /// any other literal or a lifetime
+    Other
+}
+
+#[derive(Debug)]
+struct Token<'a> {
+    kind: TokenKind<'a>,
+    line: usize
+}
+
+/// Splits Rust `source` into the tokens needed for finding the items and their
+/// attributes, comments are skipped and the lines of the tokens start at 1.
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let start = i;
+        let start_line = line;
+        let kind = match b {
+            b'\n' => {
+                line += 1;
+                i += 1;
+                continue;
+            }
+
+            _ if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i = find_byte(bytes, i, b'\n');
+                continue;
+            }
+
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // block comments might be nested
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        if bytes[i] == b'\n' {
+                            line += 1;
+                        }
+
+                        i += 1;
+                    }
+                }
+
+                continue;
+            }
+
+            b'r' | b'b' if raw_string_hashes(bytes, i).is_some() => {
+                let (quote, hashes) = raw_string_hashes(bytes, i).unwrap_or((i, 0));
+                let mut end = quote + 1;
+                while end < bytes.len() && ! (bytes[end] == b'"' && bytes[end + 1..].iter().take(hashes).filter(|b| **b == b'#').count() == hashes) {
+                    end += 1;
+                }
+
+                line += bytecount(&bytes[quote..end.min(bytes.len())], b'\n');
+                i = (end + 1 + hashes).min(bytes.len());
+                TokenKind::Str(&source[quote + 1..end.min(bytes.len())])
+            }
+
+            b'"' | b'b' if b == b'"' || bytes.get(i + 1) == Some(&b'"') => {
+                let quote = if b == b'"' { i } else { i + 1 };
+                let mut end = quote + 1;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+
+                let end = end.min(bytes.len());
+                line += bytecount(&bytes[quote..end], b'\n');
+                i = end + 1;
+                TokenKind::Str(&source[quote + 1..end])
+            }
+
+            b'\'' | b'b' if b == b'\'' || bytes.get(i + 1) == Some(&b'\'') => {
+                let quote = if b == b'\'' { i } else { i + 1 };
+                let is_char = bytes.get(quote + 1) == Some(&b'\\') || char_len_at(source, quote + 1)
+                    .map(|len| bytes.get(quote + 1 + len) == Some(&b'\''))
+                    .unwrap_or(false);
+
+                if is_char {
+                    let mut end = quote + 1;
+                    while end < bytes.len() && bytes[end] != b'\'' {
+                        end += if bytes[end] == b'\\' { 2 } else { 1 };
+                    }
+
+                    i = end + 1;
+                } else {
+                    // a lifetime or label
+                    i = quote + 1;
+                    while i < bytes.len() && is_ident_byte(bytes[i]) {
+                        i += 1;
+                    }
+                }
+
+                TokenKind::Other
+            }
+
+            _ if b.is_ascii_digit() => {
+                while i < bytes.len() && (is_ident_byte(bytes[i])
+                                          || (bytes[i] == b'.' && bytes.get(i + 1).map(u8::is_ascii_digit).unwrap_or(false))) {
+                    i += 1;
+                }
+
+                TokenKind::Other
+            }
+
+            _ if is_ident_byte(b) => {
+                while i < bytes.len() && is_ident_byte(bytes[i]) {
+                    i += 1;
+                }
+
+                TokenKind::Ident(&source[start..i])
+            }
+
+            _ => {
+                let len = char_len_at(source, i).unwrap_or(1);
+                let c = source[i..].chars().next().unwrap_or(' ');
+                i += len;
+                TokenKind::Punct(c)
+            }
+        };
+
+        tokens.push(Token { kind, line: start_line });
+    }
+
+    tokens
+}
+
+/// If a raw string - 'r"..."', 'r#"..."#' or 'br"..."' - starts at `i`,
+/// returns the position of its opening quote and the number of its '#'.
+fn raw_string_hashes(bytes: &[u8], i: usize) -> Option<(usize, usize)> {
+    if i > 0 && is_ident_byte(bytes[i - 1]) {
+        return None;
+    }
+
+    let r = if bytes[i] == b'b' { i + 1 } else { i };
+    if bytes.get(r) != Some(&b'r') {
+        return None;
+    }
+
+    let hashes = bytes[r + 1..].iter().take_while(|b| **b == b'#').count();
+    if bytes.get(r + 1 + hashes) == Some(&b'"') {
+        Some((r + 1 + hashes, hashes))
+    } else {
+        None
+    }
+}
+
+fn char_len_at(source: &str, i: usize) -> Option<usize> {
+    source.get(i..).and_then(|s| s.chars().next()).map(char::len_utf8)
+}
+
+fn is_ident_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b >= 0x80
+}
+
+fn find_byte(bytes: &[u8], from: usize, b: u8) -> usize {
+    bytes[from..].iter().position(|c| *c == b).map(|p| p + from).unwrap_or(bytes.len())
+}
+
+fn bytecount(bytes: &[u8], b: u8) -> usize {
+    bytes.iter().filter(|c| **c == b).count()
+}
+
+/// The source files of the tags in the vi tags file `tags_file`.
+fn vi_source_files(tags_file: &Path) -> RtResult<Vec<PathBuf>> {
+    let contents = fs::read(tags_file)?;
+    let mut files: Vec<PathBuf> = String::from_utf8_lossy(&contents).lines()
+        .filter(|l| ! l.starts_with('!'))
+        .filter_map(|l| l.split('\t').nth(1))
+        .map(PathBuf::from)
+        .collect();
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Removes the vi tags of the `excluded` code from `tags_file`. The line of a
+/// tag is taken from its 'line:' field, its address or by searching its pattern.
+/// The tags are kept byte by byte, because the patterns of the tags of other
+/// languages - e.g. C - might not be valid UTF-8.
+fn filter_vi_tags(tags_file: &Path, excluded: &ExcludedCode) -> RtResult<()> {
+    let contents = fs::read(tags_file)?;
+    let mut source_lines: FnvHashMap<String, Vec<String>> = FnvHashMap::default();
+    let mut filtered = Vec::with_capacity(contents.len());
+    for tag in contents.split(|b| *b == b'\n').filter(|t| ! t.is_empty()) {
+        if ! is_excluded_vi_tag(&String::from_utf8_lossy(tag), excluded, &mut source_lines) {
+            filtered.extend_from_slice(tag);
+            filtered.push(b'\n');
+        }
+    }
+
+    File::create(tags_file)?.write_all(&filtered)?;
+    Ok(())
+}
+
+fn is_excluded_vi_tag(tag: &str, excluded: &ExcludedCode, source_lines: &mut FnvHashMap<String, Vec<String>>) -> bool {
+    let mut parts = tag.splitn(3, '\t');
+    let (file, rest) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(_), Some(file), Some(rest)) if ! tag.starts_with('!') => (file, rest),
+        _ => return false
+    };
+
+    let (address, fields) = match rest.rfind(";\"\t") {
+        Some(pos) => (&rest[..pos], &rest[pos + 3..]),
+        None      => (rest, "")
+    };
+
+    let line = fields.split('\t')
+        .filter_map(|f| f.strip_prefix("line:"))
+        .filter_map(|l| l.parse().ok())
+        .next()
+        .or_else(|| address.parse().ok())
+        .or_else(|| {
+            let lines = source_lines.entry(file.to_string()).or_insert_with(|| {
+                fs::read(file).map(|c| String::from_utf8_lossy(&c).lines().map(String::from).collect()).unwrap_or_default()
+            });
+
+            pattern_line(address, lines)
+        });
+
+    line.map(|l| excluded.contains(Path::new(file), l)).unwrap_or(false)
+}
+
+/// The line of the first of `lines` matching the search pattern `address`,
+/// e.g. '/^fn foo() {$/', whose end might be truncated by ctags.
+fn pattern_line(address: &str, lines: &[String]) -> Option<usize> {
+    let pattern = address.strip_prefix("/^").or_else(|| address.strip_prefix("?^"))?;
+    let pattern = pattern.strip_suffix('/').or_else(|| pattern.strip_suffix('?')).unwrap_or(pattern);
+    let (pattern, whole_line) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None          => (pattern, false)
+    };
+
+    let pattern = pattern.replace("\\/", "/").replace("\\\\", "\\");
+    lines.iter()
+        .position(|l| if whole_line { *l == pattern } else { l.starts_with(&pattern) })
+        .map(|p| p + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::*;
+
+    fn test_filter() -> CodeFilter<'static> {
+        CodeFilter { exclude_tests: true, target_cfg: None, features: None, is_proc_macro: None }
+    }
+
+    fn kinds(source: &str) -> Vec<TokenKind<'_>> {
+        tokenize(source).into_iter().map(|t| t.kind).collect()
+    }
+
+    fn scan_lines(source: &str) -> Vec<(usize, usize)> {
+        test_filter().scan(Path::new("/src/lib.rs"), &tokenize(source)).lines
+    }
+
+    fn parse_cfg<'a>(tokens: &[Token<'a>]) -> Option<Cfg<'a>> {
+        CfgParser { tokens, pos: 0 }.parse()
+    }
+
+    #[test]
+    fn tokenize_skips_comments() {
+        assert_eq!(kinds("a // b\n/* c /* d */ e */ f"), vec![TokenKind::Ident("a"), TokenKind::Ident("f")]);
+    }
+
+    #[test]
+    fn tokenize_counts_lines() {
+        let lines: Vec<_> = tokenize("a\n/*\n*/\n\"x\ny\" b").iter().map(|t| t.line).collect();
+        assert_eq!(lines, vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn tokenize_strings() {
+        assert_eq!(kinds(r##""a\"}" b"{" r"}" r#"x"y"#"##),
+                   vec![TokenKind::Str(r#"a\"}"#), TokenKind::Str("{"), TokenKind::Str("}"), TokenKind::Str(r#"x"y"#)]);
+    }
+
+    #[test]
+    fn tokenize_chars_and_lifetimes() {
+        assert_eq!(kinds("'}' '\\'' 'a b"), vec![TokenKind::Other, TokenKind::Other, TokenKind::Other, TokenKind::Ident("b")]);
+    }
+
+    #[test]
+    fn tokenize_punctuation_and_numbers() {
+        assert_eq!(kinds("#[x(1.5)]"),
+                   vec![TokenKind::Punct('#'), TokenKind::Punct('['), TokenKind::Ident("x"), TokenKind::Punct('('),
+                        TokenKind::Other, TokenKind::Punct(')'), TokenKind::Punct(']')]);
+    }
+
+    #[test]
+    fn item_end_of_block() {
+        let tokens = tokenize("fn a() { b(); } fn c() {}");
+        assert_eq!(item_end(&tokens, 0), 9);
+    }
+
+    #[test]
+    fn item_end_of_semicolon_item() {
+        let tokens = tokenize("struct A(u8); fn b() {}");
+        assert_eq!(item_end(&tokens, 0), 5);
+    }
+
+    #[test]
+    fn item_end_of_enum_variant() {
+        let tokens = tokenize("A(u8), B }");
+        assert_eq!(item_end(&tokens, 0), 4);
+        assert_eq!(item_end(&tokens, 5), 5);
+    }
+
+    #[test]
+    fn item_end_of_unclosed_item() {
+        let tokens = tokenize("fn a() {");
+        assert_eq!(item_end(&tokens, 0), 4);
+    }
+
+    #[test]
+    fn cfg_parser_option() {
+        match parse_cfg(&tokenize("feature = \"std\"")) {
+            Some(Cfg::Option("feature", Some("std"))) => {}
+            _ => panic!("expected the option 'feature = \"std\"'")
+        }
+    }
+
+    #[test]
+    fn cfg_parser_nested() {
+        match parse_cfg(&tokenize("all(unix, not(test), any(a, b = \"c\"),)")) {
+            Some(Cfg::All(ref cfgs)) => {
+                assert_eq!(cfgs.len(), 3);
+                assert!(matches!(cfgs[0], Cfg::Option("unix", None)));
+                assert!(matches!(cfgs[1], Cfg::Not(_)));
+                assert!(matches!(cfgs[2], Cfg::Any(ref any) if any.len() == 2));
+            }
+
+            _ => panic!("expected an 'all' predicate")
+        }
+    }
+
+    #[test]
+    fn cfg_parser_invalid() {
+        assert!(parse_cfg(&tokenize("foo(a)")).is_none());
+        assert!(parse_cfg(&tokenize("not(a, b)")).is_none());
+        assert!(parse_cfg(&tokenize("a = b")).is_none());
+        assert!(parse_cfg(&tokenize("all(a")).is_none());
+    }
+
+    #[test]
+    fn cfg_eval() {
+        let features = vec!["std".to_string()];
+        let filter = CodeFilter { exclude_tests: true, target_cfg: None, features: Some(&features), is_proc_macro: Some(true) };
+        let eval = |source| parse_cfg(&tokenize(source)).and_then(|cfg| cfg.eval(&filter));
+        assert_eq!(eval("test"), Some(false));
+        assert_eq!(eval("not(test)"), Some(true));
+        assert_eq!(eval("feature = \"std\""), Some(true));
+        assert_eq!(eval("feature = \"alloc\""), Some(false));
+        assert_eq!(eval("all(test, unknown)"), Some(false));
+        assert_eq!(eval("any(test, unknown)"), None);
+        assert_eq!(eval("any(feature = \"std\", unknown)"), Some(true));
+        assert_eq!(eval("proc_macro"), Some(true));
+        assert_eq!(eval("not(proc_macro)"), Some(false));
+    }
+
+    #[test]
+    fn scan_test_module() {
+        assert_eq!(scan_lines("fn a() {}\n#[cfg(test)]\nmod tests {\n    fn b() {}\n}\nfn c() {}"), vec![(2, 5)]);
+    }
+
+    #[test]
+    fn scan_test_functions() {
+        assert_eq!(scan_lines("#[test]\nfn a() {}\n#[tokio::test]\nasync fn b() {}\n#[inline]\nfn c() {}"),
+                   vec![(1, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn scan_enum_variant() {
+        assert_eq!(scan_lines("enum E {\n    #[cfg(test)]\n    A,\n    B\n}"), vec![(2, 3)]);
+    }
+
+    #[test]
+    fn scan_inner_attribute() {
+        assert_eq!(scan_lines("#![cfg(test)]\nfn a() {}"), vec![(1, usize::MAX)]);
+        assert!(scan_lines("mod a {\n    #![cfg(test)]\n}").is_empty());
+    }
+
+    #[test]
+    fn scan_unclosed_attribute() {
+        assert!(scan_lines("fn a() {}\n#[").is_empty());
+        assert!(scan_lines("fn a() {}\n#![").is_empty());
+        assert!(scan_lines("#[cfg(test)]").is_empty());
+    }
+
+    #[test]
+    fn scan_file_modules() {
+        let code = test_filter().scan(Path::new("/src/foo.rs"),
+                                      &tokenize("mod inner {\n    #[cfg(test)]\n    mod tests;\n}\n#[cfg(test)]\n#[path = \"t.rs\"]\nmod other;"));
+
+        assert_eq!(code.module_paths, vec![PathBuf::from("/src/foo/inner/tests.rs"), PathBuf::from("/src/foo/inner/tests"),
+                                           PathBuf::from("/src/t.rs")]);
+    }
+
+    #[test]
+    fn enables_features() {
+        assert!(enables_feature("#![feature(rustc_private)]\nfn a() {}", "rustc_private"));
+        assert!(enables_feature("//! docs\n#![feature(\n    box_patterns,\n    rustc_private\n)]", "rustc_private"));
+        assert!(enables_feature("#![cfg_attr(feature = \"rustc\", feature(rustc_private))]", "rustc_private"));
+        assert!(! enables_feature("#![feature(box_patterns)]\n#![allow(rustc_private)]", "rustc_private"));
+        assert!(! enables_feature("// #![feature(rustc_private)]\n#[cfg(feature = \"rustc_private\")]\nfn a() {}", "rustc_private"));
+        assert!(! enables_feature("#![feature(", "rustc_private"));
+    }
+}
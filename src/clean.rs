@@ -0,0 +1,308 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use fnv::FnvHashSet;
+
+use rt_result::RtResult;
+use config::Config;
+use types::{DepTree, Source, TagsKind, is_stale_lock};
+use dirs::{rusty_tags_cache_dir, rusty_tags_locks_dir, rusty_tags_mirror_dir};
+use cache::{self, CacheInfo};
+use tags::vim_tags_list_file;
+use std_lib::{self, StdLibSrc};
+
+/// Removes the tags files, cached tags and stale locks created by rusty-tags
+/// for the sources of `dep_tree`, the workspace tags and the tags of the
+/// standard library. With `dry_run` the files are only listed.
+pub fn clean_project(config: &Config, dep_tree: &DepTree, dry_run: bool) -> RtResult<()> {
+    let mut cleaner = Cleaner::new(config);
+    for source in dep_tree.reachable_sources() {
+        cleaner.add_source(source)?;
+    }
+
+    if let Some(workspace_root) = dep_tree.workspace_root() {
+        for kind in &config.tags_spec.kinds {
+            cleaner.add_tags_file(&workspace_root.join(config.tags_spec.file_name(*kind)));
+            cleaner.add_tags_file(&workspace_root.join(config.tags_spec.member_file_name(*kind)));
+        }
+    }
+
+    cleaner.add_std_lib_tags()?;
+    cleaner.remove(dry_run)
+}
+
+/// Removes the tags files of all sources known by the cache, the cache
+/// entries, the tags files inside of the mirror directory, stale locks
+/// and the tags of the standard library. With `dry_run` the files are
+/// only listed.
+pub fn clean_all(config: &Config, dry_run: bool) -> RtResult<()> {
+    let mut cleaner = Cleaner::new(config);
+    for entry in fs::read_dir(rusty_tags_cache_dir()?)? {
+        let path = entry?.path();
+        let is_tags = config.tags_spec.kinds.iter()
+            .any(|k| path.extension().and_then(|e| e.to_str()) == Some(k.file_extension()));
+
+        if is_tags {
+            if let Some(tags_file) = CacheInfo::load(&path).and_then(|i| i.tags_file) {
+                cleaner.add_tags_file(&tags_file);
+            }
+
+            cleaner.add_cache_entry(&path);
+        } else if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            // meta data of an already removed cache entry
+            cleaner.add_file(&path);
+        }
+    }
+
+    cleaner.add_mirrored_tags_files(rusty_tags_mirror_dir()?)?;
+    for entry in fs::read_dir(rusty_tags_locks_dir()?)? {
+        cleaner.add_lock(&entry?.path());
+    }
+
+    cleaner.add_std_lib_tags()?;
+    cleaner.remove(dry_run)
+}
+
+/// Collects the files to remove, only files recognized
+/// as created by rusty-tags are collected.
+struct Cleaner<'a> {
+    config: &'a Config,
+
+    /// the files to remove
+    files: Vec<PathBuf>,
+
+    /// the already collected or skipped files
+    known_files: FnvHashSet<PathBuf>
+}
+
+impl<'a> Cleaner<'a> {
+    fn new(config: &'a Config) -> Cleaner<'a> {
+        Cleaner { config, files: Vec::new(), known_files: FnvHashSet::default() }
+    }
+
+    fn add_source(&mut self, source: &Source) -> RtResult<()> {
+        for kind in &self.config.tags_spec.kinds {
+            for tags_file in source.possible_tags_files(self.config, *kind)? {
+                self.add_tags_file(&tags_file);
+            }
+
+        }
+
+        // the cached tags of all configurations are named '{name}-{hash}-{key id}.{ext}'
+        self.add_cache_entries(&format!("{}-{}-", source.name, source.hash))?;
+
+        // the lock files are named '{name}-{hash}.lock', the ones of older
+        // versions of rusty-tags '{name}-{hash}.{kinds extension}'
+        let lock_prefix = format!("{}-{}.", source.name, source.hash);
+        for entry in fs::read_dir(rusty_tags_locks_dir()?)? {
+            let path = entry?.path();
+            if path.file_name().map(|n| n.to_string_lossy().starts_with(&lock_prefix)).unwrap_or(false) {
+                self.add_lock(&path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the tags file and for vi tags its vim script with the list
+    /// of tags files, if they're recognized as created by rusty-tags.
+    fn add_tags_file(&mut self, tags_file: &Path) {
+        if ! tags_file.is_file() || self.known_files.contains(tags_file) {
+            return;
+        }
+
+        let config = self.config;
+        if is_rusty_tags_file(config, tags_file) {
+            self.add_file(tags_file);
+        } else {
+            self.known_files.insert(tags_file.to_path_buf());
+            verbose!(config, "Skipping '{}', which isn't recognized as tags file created by rusty-tags", tags_file.display());
+        }
+
+        let tags_list_file = vim_tags_list_file(tags_file);
+        if tags_list_file.is_file() && is_rusty_tags_file(self.config, &tags_list_file) {
+            self.add_file(&tags_list_file);
+        }
+    }
+
+    fn add_cache_entry(&mut self, cached_tags_file: &Path) {
+        self.add_file(cached_tags_file);
+        self.add_file(&cache::info_file(cached_tags_file));
+    }
+
+    /// Adds the cached tags - of the configured kinds - named '{prefix}{key id}.{ext}'.
+    fn add_cache_entries(&mut self, prefix: &str) -> RtResult<()> {
+        for entry in fs::read_dir(rusty_tags_cache_dir()?)? {
+            let path = entry?.path();
+            let key_id = path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.strip_prefix(prefix));
+
+            let has_kind = path.extension()
+                .map(|e| self.config.tags_spec.kinds.iter().any(|k| e == k.file_extension()))
+                .unwrap_or(false);
+
+            if let Some(key_id) = key_id {
+                if has_kind && ! key_id.contains('-') && ! key_id.contains('.') {
+                    self.add_cache_entry(&path);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_lock(&mut self, lock_file: &Path) {
+        if ! lock_file.is_file() {
+            return;
+        }
+
+        let config = self.config;
+        if is_stale_lock(lock_file) {
+            self.add_file(lock_file);
+        } else {
+            info!(config, "Skipping lock '{}', which might be held by a running rusty-tags", lock_file.display());
+        }
+    }
+
+    fn add_mirrored_tags_files(&mut self, dir: &Path) -> RtResult<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.add_mirrored_tags_files(&path)?;
+            } else if is_tags_file_name(self.config, &path) {
+                self.add_tags_file(&path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_std_lib_tags(&mut self) -> RtResult<()> {
+        let (src_dir, toolchain) = match std_lib::find_src_dir(self.config)? {
+            StdLibSrc::Found { src_path, toolchain } => (Some(src_path), Some(toolchain)),
+            StdLibSrc::Missing { toolchain }         => (None, Some(toolchain)),
+            StdLibSrc::NoToolchain                   => (None, None)
+        };
+
+        if let Some(dir) = self.config.output_dir_std.clone().or(src_dir) {
+            for kind in &self.config.tags_spec.kinds {
+                let std_lib_tags = dir.join(self.config.tags_spec.file_name(*kind));
+                self.add_tags_file(&std_lib_tags);
+
+                // the meta data of the copied tags, see 'update_std_lib_tags'
+                self.add_file(&cache::info_file(&std_lib_tags));
+            }
+        }
+
+        if let Some(toolchain) = toolchain {
+            for prefix in std_lib::all_cached_tags_prefixes(&toolchain) {
+                self.add_cache_entries(&prefix)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, file: &Path) {
+        if file.is_file() && self.known_files.insert(file.to_path_buf()) {
+            self.files.push(file.to_path_buf());
+        }
+    }
+
+    fn remove(self, dry_run: bool) -> RtResult<()> {
+        let config = self.config;
+        if dry_run {
+            for file in &self.files {
+                println!("Would remove '{}'", file.display());
+            }
+
+            info!(config, "Would remove {} files", self.files.len());
+            return Ok(());
+        }
+
+        let mirror_dir = rusty_tags_mirror_dir()?;
+        for file in &self.files {
+            verbose!(config, "Removing '{}'", file.display());
+            fs::remove_file(file)?;
+
+            // remove the directories inside of the mirror directory which became empty
+            if file.starts_with(mirror_dir) {
+                for dir in file.ancestors().skip(1).take_while(|d| *d != mirror_dir) {
+                    if fs::remove_dir(dir).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        info!(config, "Removed {} files", self.files.len());
+        Ok(())
+    }
+}
+
+/// if the file name of `file` is one of the names used for tags files
+fn is_tags_file_name(config: &Config, file: &Path) -> bool {
+    let file_name = match file.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None       => return false
+    };
+
+    config.tags_spec.kinds.iter().any(|k| {
+        let tags_name = config.tags_spec.file_name(*k);
+        file_name == tags_name || (*k == TagsKind::Vi && file_name == format!("{}.vim", tags_name))
+    })
+}
+
+/// If `file` was created by rusty-tags, which is recognized by its contents:
+/// vi tags start with the '!_TAG_' pseudo tags, emacs tags with a form feed
+/// and the vim scripts with the lists of tags files with a rusty-tags comment.
+/// Empty files are only recognized by their name.
+fn is_rusty_tags_file(config: &Config, file: &Path) -> bool {
+    let mut start = Vec::with_capacity(128);
+    let read = File::open(file).and_then(|f| f.take(128).read_to_end(&mut start));
+    if read.is_err() {
+        return false;
+    }
+
+    if start.is_empty() {
+        return is_tags_file_name(config, file);
+    }
+
+    start.starts_with(b"!_TAG_")
+        || start.starts_with(b"\x0c\n")
+        || (start.starts_with(b"\" tags files of '") && String::from_utf8_lossy(&start).contains("created by rusty-tags"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn rusty_tags_files_by_contents() {
+        let dir = TempDir::new().unwrap();
+        let config = Config::for_tests(dir.path());
+        let file = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+            path
+        };
+
+        assert!(is_rusty_tags_file(&config, &file("rusty-tags.vi", "!_TAG_FILE_FORMAT\t2\nfoo\tsrc/lib.rs\t/^fn foo() {$/;\"\tf\n")));
+        assert!(is_rusty_tags_file(&config, &file("rusty-tags.emacs", "\x0c\nsrc/lib.rs,14\nfn foo() {\x7ffoo\x011,0\n")));
+        assert!(is_rusty_tags_file(&config, &file("rusty-tags.vi.vim", "\" tags files of 'proj', created by rusty-tags\nsetlocal tags=a.vi\n")));
+
+        // empty files are only recognized by their name
+        assert!(! is_rusty_tags_file(&config, &file("rusty-tags.vi.empty", "")));
+        assert!(! is_rusty_tags_file(&config, &file("empty.vi", "")));
+        assert!(is_rusty_tags_file(&config, &file("rusty-tags.vi", "")));
+
+        assert!(! is_rusty_tags_file(&config, &file("tags", "some notes\n")));
+        assert!(! is_rusty_tags_file(&config, &file("tags.vim", "\" tags files of 'proj'\n")));
+        assert!(! is_rusty_tags_file(&config, &dir.path().join("missing.vi")));
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Output, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use fnv::{FnvHashMap, FnvHashSet};
+use tempfile::NamedTempFile;
+use wait_timeout::ChildExt;
+
+use rt_result::RtResult;
+
+/// The exit code if rusty-tags was interrupted by SIGINT or SIGTERM.
+pub const EXIT_CODE_INTERRUPTED: i32 = 130;
+
+lazy_static! {
+    static ref RESOURCES: Mutex<Resources> = Mutex::new(Resources::default());
+}
+
+/// The resources of the running rusty-tags which have
+/// to be cleaned up if it gets interrupted.
+#[derive(Default)]
+struct Resources {
+    /// the temporary files, which are removed
+    temp_files: FnvHashSet<PathBuf>,
+
+    /// the temporary directories, which are removed
+    temp_dirs: FnvHashSet<PathBuf>,
+
+    /// the held lock files, which are cleared
+    lock_files: FnvHashSet<PathBuf>,
+
+    /// the running child processes, which are killed
+    children: FnvHashMap<u32, Arc<Mutex<Child>>>
+}
+
+/// Installs the handler for SIGINT and SIGTERM - or Ctrl-C on windows - which kills
+/// the running child processes, removes the temporary files, clears the held lock
+/// files and exits with `EXIT_CODE_INTERRUPTED`.
+pub fn install_signal_handler() -> RtResult<()> {
+    ctrlc::set_handler(|| {
+        // the resources stay locked, so that no further
+        // resources are created by other threads
+        resources().release();
+        process::exit(EXIT_CODE_INTERRUPTED);
+    })?;
+
+    Ok(())
+}
+
+impl Resources {
+    /// Kills the child processes, removes the temporary files and clears the lock files.
+    fn release(&self) {
+        for child in self.children.values() {
+            if let Ok(mut child) = child.lock() {
+                let _ = child.kill();
+            }
+        }
+
+        for file in &self.temp_files {
+            let _ = fs::remove_file(file);
+        }
+
+        for dir in &self.temp_dirs {
+            let _ = fs::remove_dir_all(dir);
+        }
+
+        // the OS level advisory locks are released by the exit
+        for file in &self.lock_files {
+            if let Ok(file) = OpenOptions::new().write(true).open(file) {
+                let _ = file.set_len(0);
+            }
+        }
+    }
+}
+
+/// A `NamedTempFile` which is also removed if rusty-tags gets interrupted.
+pub struct TempFile {
+    file: NamedTempFile
+}
+
+impl TempFile {
+    pub fn new_in<P: AsRef<Path>>(dir: P) -> RtResult<TempFile> {
+        Ok(TempFile::create_in(dir)?)
+    }
+
+    /// Like 'new_in', but keeps the io error, e.g. to find out if `dir` isn't writable.
+    pub fn create_in<P: AsRef<Path>>(dir: P) -> io::Result<TempFile> {
+        let mut resources = resources();
+        let file = NamedTempFile::new_in(dir)?;
+        resources.temp_files.insert(file.path().to_path_buf());
+        Ok(TempFile { file })
+    }
+}
+
+impl Deref for TempFile {
+    type Target = NamedTempFile;
+
+    fn deref(&self) -> &NamedTempFile {
+        &self.file
+    }
+}
+
+impl DerefMut for TempFile {
+    fn deref_mut(&mut self) -> &mut NamedTempFile {
+        &mut self.file
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        resources().temp_files.remove(self.file.path());
+    }
+}
+
+/// Registers the temporary directory `dir`, which is removed if rusty-tags gets interrupted.
+pub fn register_temp_dir(dir: &Path) {
+    resources().temp_dirs.insert(dir.to_path_buf());
+}
+
+/// Registers the held lock file `file`, which is cleared if rusty-tags gets interrupted.
+pub fn register_lock_file(file: &Path) {
+    resources().lock_files.insert(file.to_path_buf());
+}
+
+pub fn unregister_lock_file(file: &Path) {
+    resources().lock_files.remove(file);
+}
+
+/// Runs `cmd` like `Command::output`, but the child process
+/// is killed if rusty-tags gets interrupted.
+pub fn run(cmd: &mut Command) -> io::Result<Output> {
+    run_with_timeout(cmd, None)
+}
+
+/// Runs `cmd` like `run`, but the child process is killed if it doesn't
+/// finish within `timeout`, which is reported as an error of the kind
+/// `io::ErrorKind::TimedOut`.
+pub fn run_with_timeout(cmd: &mut Command, timeout: Option<Duration>) -> io::Result<Output> {
+    let (child, stdout_reader, stderr_reader) = {
+        let mut resources = resources();
+        let mut child = cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // read the output in separate threads, otherwise the child
+        // might block on writing into a full pipe
+        let stdout_reader = read_in_thread(child.stdout.take());
+        let stderr_reader = read_in_thread(child.stderr.take());
+
+        let child = Arc::new(Mutex::new(child));
+        resources.children.insert(child_id(&child), child.clone());
+        (child, stdout_reader, stderr_reader)
+    };
+
+    // the child is only locked for short periods, so that
+    // the signal handler is able to kill it
+    let start = Instant::now();
+    let status = loop {
+        let status = lock(&child).wait_timeout(Duration::from_millis(50));
+        match status {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) if timeout.map(|t| start.elapsed() >= t).unwrap_or(false) => {
+                let mut child = lock(&child);
+                let _ = child.kill();
+                let _ = child.wait();
+                break Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+            }
+
+            Ok(None) => continue,
+            Err(err) => break Err(err)
+        }
+    };
+
+    resources().children.remove(&child_id(&child));
+    let status = status?;
+
+    Ok(Output {
+        status,
+        stdout: stdout_reader.join().unwrap_or_default(),
+        stderr: stderr_reader.join().unwrap_or_default()
+    })
+}
+
+fn read_in_thread<R: Read + Send + 'static>(reader: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut contents = Vec::new();
+        if let Some(mut reader) = reader {
+            let _ = reader.read_to_end(&mut contents);
+        }
+
+        contents
+    })
+}
+
+fn child_id(child: &Arc<Mutex<Child>>) -> u32 {
+    lock(child).id()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn resources() -> MutexGuard<'static, Resources> {
+    lock(&RESOURCES)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn registered_temp_files() {
+        let dir = TempDir::new().unwrap();
+        let path = {
+            let temp_file = TempFile::new_in(dir.path()).unwrap();
+            let path = temp_file.path().to_path_buf();
+            assert!(resources().temp_files.contains(&path));
+            path
+        };
+
+        assert!(! path.exists());
+        assert!(! resources().temp_files.contains(&path));
+    }
+
+    #[test]
+    fn release_resources() {
+        let dir = TempDir::new().unwrap();
+        let temp_file = dir.path().join("tmp.vi");
+        let temp_dir = dir.path().join("tmp");
+        let lock_file = dir.path().join("proj-1234.lock");
+        File::create(&temp_file).unwrap();
+        fs::create_dir(&temp_dir).unwrap();
+        File::create(temp_dir.join("tags")).unwrap();
+        File::create(&lock_file).unwrap().write_all(b"{}").unwrap();
+
+        let child = Command::new("sleep").arg("10").spawn().unwrap();
+        let child = Arc::new(Mutex::new(child));
+
+        let mut resources = Resources::default();
+        resources.temp_files.insert(temp_file.clone());
+        resources.temp_dirs.insert(temp_dir.clone());
+        resources.lock_files.insert(lock_file.clone());
+        resources.children.insert(child_id(&child), child.clone());
+        resources.release();
+
+        assert!(! temp_file.exists());
+        assert!(! temp_dir.exists());
+        assert_eq!(fs::metadata(&lock_file).unwrap().len(), 0);
+        assert!(! lock(&child).wait().unwrap().success());
+    }
+}
@@ -3,15 +3,24 @@ use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::Read;
 use std::cmp::max;
-use std::process::Command;
-use clap::App;
-use types::{TagsExe, TagsKind, TagsSpec};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use types::{ExtraTargets, LockWait, TagsKind, TagsSpec, TagsOutput};
+use cache::{self, TagsKey};
+use cfg::TargetCfg;
+use probe;
 use rt_result::RtResult;
 use dirs;
+use glob;
+use cleanup;
 use tempfile::TempDir;
 
 /// the configuration used to run rusty-tags
 pub struct Config {
+    /// what rusty-tags should do
+    pub command: Command,
+
     /// the tags that should be created
     pub tags_spec: TagsSpec,
 
@@ -21,12 +30,92 @@ pub struct Config {
     /// output directory for the tags for the standard library
     pub output_dir_std: Option<PathBuf>,
 
+    /// where the tags of the cargo project are written to, if
+    /// they shouldn't be written beside of its 'Cargo.toml'
+    pub output: Option<TagsOutput>,
+
+    /// the workspace members for which tags are created
+    pub packages: Vec<String>,
+
+    /// the workspace members for which no tags are created
+    pub exclude_packages: Vec<String>,
+
+    /// create tags for all workspace members, instead of
+    /// only for the one containing the start directory
+    pub all_members: bool,
+
     /// do not generate tags for dependencies
     pub omit_deps: bool,
 
     /// forces the recreation of cached tags
     pub force_recreate: bool,
 
+    /// how long to wait for the locks held by other running instances of rusty-tags
+    pub lock_wait: LockWait,
+
+    /// the maximal duration of a ctags run, after which ctags is killed
+    pub ctags_timeout: Option<Duration>,
+
+    /// continue with the other sources if ctags fails or times out for a source
+    pub keep_going: bool,
+
+    /// inline the tags of dependencies into emacs tags files
+    /// instead of referencing them with include directives
+    pub inline_includes: bool,
+
+    /// write the tags files of dependencies into the mirror
+    /// directory instead of their source directories
+    pub mirror_deps: bool,
+
+    /// only create the tags of each source and lists of the tags files
+    /// for the roots, instead of merging the tags of the dependencies
+    pub tags_list: bool,
+
+    /// create an aggregate tags file of all workspace members
+    /// at the workspace root
+    pub workspace_tags: bool,
+
+    /// include the tags of the standard library into the tags of the roots
+    pub include_std: bool,
+
+    /// include the tags of the compiler crates into the
+    /// tags of the roots using the 'rustc_private' feature
+    pub rustc_dev: bool,
+
+    /// create the tags of the code generated by build scripts into 'OUT_DIR'
+    pub out_dir_tags: bool,
+
+    /// the languages - beside of Rust - tagged for the sources
+    /// whose names match the glob patterns, e.g. '*-sys' => ['C']
+    pub extra_languages: Vec<(String, Vec<String>)>,
+
+    /// the examples, benches and tests tagged for the roots
+    pub root_targets: ExtraTargets,
+
+    /// the examples, benches and tests tagged for the dependencies
+    pub dep_targets: ExtraTargets,
+
+    /// the files and directories excluded from the tags of all sources,
+    /// with the syntax of '.gitignore' files
+    pub exclude_paths: Vec<String>,
+
+    /// the files and directories excluded from the tags of
+    /// the sources whose names match the glob patterns
+    pub crate_exclude_paths: Vec<(String, Vec<String>)>,
+
+    /// respect the '.gitignore' and '.ignore' files of roots and path sources
+    pub gitignore: bool,
+
+    /// exclude the tags of '#[cfg(test)]' items and '#[test]' functions of the dependencies
+    pub exclude_test_code: bool,
+
+    /// exclude the tags of '#[cfg(test)]' items and '#[test]' functions of the roots
+    pub exclude_root_test_code: bool,
+
+    /// the cfg options of the target, if the '#[cfg]' predicates are evaluated
+    /// and the tags of the items whose predicates are false are excluded
+    pub target_cfg: Option<TargetCfg>,
+
     /// verbose output about all operations
     pub verbose: bool,
 
@@ -40,6 +129,44 @@ pub struct Config {
     temp_dir: TempDir
 }
 
+/// The command executed by rusty-tags.
+#[derive(Eq, PartialEq, Debug)]
+pub enum Command {
+    /// create the tags of the cargo project
+    UpdateTags,
+
+    /// manage the cached tags in '~/.rusty-tags/cache'
+    Cache(CacheCommand),
+
+    /// remove the files created by rusty-tags
+    Clean {
+        /// remove the files of all sources known by the cache,
+        /// instead of only the ones of the cargo project
+        all: bool,
+
+        /// only list the files that would be removed
+        dry_run: bool
+    }
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum CacheCommand {
+    /// print statistics about the cache
+    Stats,
+
+    /// remove the cached tags of sources that don't exist anymore
+    Prune,
+
+    /// remove the least recently used cached tags
+    Gc {
+        /// remove cached tags until the cache doesn't exceed this size in bytes
+        max_size: Option<u64>,
+
+        /// remove cached tags that weren't used for this duration
+        older_than: Option<Duration>
+    }
+}
+
 impl Config {
    pub fn from_command_args() -> RtResult<Config> {
        let matches = App::new("rusty-tags")
@@ -47,17 +174,102 @@ impl Config {
            // Pull version from Cargo.toml
            .version(crate_version!())
            .author("Daniel Trstenjak <daniel.trstenjak@gmail.com>")
-           .arg_from_usage("<TAGS_KIND> 'The kind of the created tags (vi, emacs)'")
+           .setting(AppSettings::SubcommandsNegateReqs)
+           .arg_from_usage("<TAGS_KIND> 'The kinds of the created tags (vi, emacs, vi,emacs or all)'")
            .arg_from_usage("-s --start-dir [DIR] 'Start directory for the search of the Cargo.toml (default: current working directory)'")
-           .arg_from_usage("--output-dir-std [DIR] 'Set the output directory for the tags for the Rust standard library (default: $RUST_SRC_PATH)'")
+           .arg_from_usage("--output-dir-std [DIR] 'Set the output directory for the tags for the Rust standard library (default: its source directory)'")
+           .arg(Arg::from_usage("-p --package [SPEC]... 'Only create tags for the given workspace members (default: the member containing the start directory)'")
+                .number_of_values(1))
+           .arg(Arg::from_usage("--exclude [SPEC]... 'Exclude the given workspace members'")
+                .number_of_values(1))
+           .arg_from_usage("--workspace 'Create tags for all workspace members'")
            .arg_from_usage("-o --omit-deps 'Do not generate tags for dependencies'")
+           .arg(Arg::from_usage("--wait [SECS] 'Wait for other running instances of rusty-tags creating the same tags, at most SECS seconds if given, instead of skipping'")
+                .min_values(0)
+                .require_equals(true))
+           .arg_from_usage("--ctags-timeout [SECS] 'Kill a ctags run for a source after SECS seconds (default: no timeout)'")
+           .arg_from_usage("-k --keep-going 'Continue with the other sources if ctags fails or times out for a source'")
            .arg_from_usage("-f --force-recreate 'Forces the recreation of the tags of all dependencies and the Rust standard library'")
-           .arg_from_usage("-v --verbose 'Verbose output about all operations'")
-           .arg_from_usage("-q --quiet 'Don't output anything but errors'")
+           .arg_from_usage("--mirror-deps 'Write the tags files of dependencies into ~/.rusty-tags/mirror instead of their source directories'")
+           .arg_from_usage("--tags-list 'Only create the tags of each crate and a list of all tags files for the cargo project, instead of merging the tags of dependencies'")
+           .arg_from_usage("--workspace-tags 'Create a tags file at the workspace root containing the tags of all workspace members and their dependencies'")
+           .arg_from_usage("--include-std 'Include the tags of the standard library - core, alloc, std and for proc-macro crates proc_macro - into the tags of the cargo project'")
+           .arg_from_usage("--rustc-dev 'Include the tags of the compiler crates of the rustc-dev component into the tags of workspace members using the rustc_private feature'")
+           .arg_from_usage("--out-dir-tags 'Also create tags for the code generated by build scripts into OUT_DIR, which are run by cargo check'")
+           .arg(Arg::from_usage("--extra-languages [PATTERN=LANGS]... 'Also tag the languages LANGS - e.g. C,C++ - in the crates whose names match the glob PATTERN, e.g. *-sys=C'")
+                .number_of_values(1))
+           .arg_from_usage("--root-targets [KINDS] 'The targets tagged for workspace members beside of libraries and binaries (examples, benches, tests, all or none, default: all)'")
+           .arg_from_usage("--dep-targets [KINDS] 'The targets tagged for dependencies beside of libraries and binaries (examples, benches, tests, all or none, default: none)'")
+           .arg(Arg::from_usage("--exclude-path [GLOB]... 'Exclude the files and directories matching GLOB - with the syntax of .gitignore files - from the tags, e.g. tests/fixtures/'")
+                .number_of_values(1))
+           .arg_from_usage("--gitignore 'Exclude the files ignored by .gitignore and .ignore files from the tags of workspace members and path dependencies'")
+           .arg_from_usage("--exclude-test-code 'Exclude the tags of #[cfg(test)] items and #[test] functions of dependencies'")
+           .arg_from_usage("--exclude-root-test-code 'Exclude the tags of #[cfg(test)] items and #[test] functions of workspace members'")
+           .arg_from_usage("--eval-cfg 'Evaluate #[cfg] predicates for the target and the enabled features, and exclude the tags of the items whose predicates are false'")
+           .arg_from_usage("--target [TRIPLE] 'The target whose cfg options are used by --eval-cfg, implies --eval-cfg (default: the host)'")
+           .arg_from_usage("--inline-includes 'Inline the tags of dependencies into emacs tags files instead of using include directives'")
+           .arg(Arg::from_usage("-v --verbose 'Verbose output about all operations'").global(true))
+           .arg(Arg::from_usage("-q --quiet 'Don't output anything but errors'").global(true))
            .arg_from_usage("-n --num-threads [NUM] 'Num threads used for the tags creation (default: num available physical cpus)'")
-           .arg_from_usage("-O --output [FILENAME] 'Name of output tags file.'")
+           .arg_from_usage("-O --output [FILENAME] 'Name of output tags file, or a path the tags of the cargo project are written to, or - to write them to stdout'")
+           .subcommand(SubCommand::with_name("cache")
+                .about("Manage the cached tags in ~/.rusty-tags/cache")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("stats")
+                     .about("Print the size and the number of entries of the cache, in total and per crate"))
+                .subcommand(SubCommand::with_name("prune")
+                     .about("Remove the cached tags of sources whose directory doesn't exist anymore and of not installed toolchains"))
+                .subcommand(SubCommand::with_name("gc")
+                     .about("Remove the least recently used cached tags")
+                     .setting(AppSettings::ArgRequiredElseHelp)
+                     .arg_from_usage("--max-size [SIZE] 'Remove the least recently used cached tags until the cache doesn't exceed SIZE, e.g. 500M or 2G'")
+                     .arg_from_usage("--older-than [DURATION] 'Remove the cached tags not used for DURATION, e.g. 30d, 12h or 2w'")))
+           .subcommand(SubCommand::with_name("clean")
+                .about("Remove the tags files, cached tags and stale locks created for the cargo project and its dependencies")
+                .arg_from_usage("--all 'Remove the files created for all sources known by the cache, not only the ones of the cargo project'")
+                .arg_from_usage("--dry-run 'Only list the files that would be removed'"))
            .get_matches();
 
+       let command = match matches.subcommand() {
+           ("cache", Some(cache_matches)) => {
+               let cache_cmd = match cache_matches.subcommand() {
+                   ("stats", _) => CacheCommand::Stats,
+                   ("prune", _) => CacheCommand::Prune,
+                   ("gc", Some(gc_matches)) => {
+                       let max_size = match gc_matches.value_of("max-size") {
+                           Some(size) => Some(cache::parse_size(size)?),
+                           None       => None
+                       };
+
+                       let older_than = match gc_matches.value_of("older-than") {
+                           Some(duration) => Some(cache::parse_duration(duration)?),
+                           None           => None
+                       };
+
+                       CacheCommand::Gc { max_size, older_than }
+                   }
+
+                   _ => return Err("Missing cache command, expected one of: stats, prune, gc".into())
+               };
+
+               Command::Cache(cache_cmd)
+           }
+
+           ("clean", Some(clean_matches)) => Command::Clean {
+               all: clean_matches.is_present("all"),
+               dry_run: clean_matches.is_present("dry-run")
+           },
+
+           _ => Command::UpdateTags
+       };
+
+       // the global options might be given after the subcommands
+       let is_subcmd_present = |name: &str| {
+           matches.subcommand().1
+               .map(|m| m.is_present(name) || m.subcommand().1.map(|sm| sm.is_present(name)).unwrap_or(false))
+               .unwrap_or(false)
+       };
+
        let start_dir = matches.value_of("start-dir")
            .map(PathBuf::from)
            .unwrap_or(env::current_dir()?);
@@ -74,37 +286,67 @@ impl Config {
            }
        }
 
-       let kind = value_t_or_exit!(matches.value_of("TAGS_KIND"), TagsKind);
-
-       let (vi_tags, emacs_tags, ctags_exe, ctags_options) = {
-           let mut vt = "rusty-tags.vi".to_string();
-           let mut et = "rusty-tags.emacs".to_string();
-           let mut cte = None;
-           let mut cto = "".to_string();
+       let kinds = match command {
+           Command::UpdateTags => parse_tags_kinds(matches.value_of("TAGS_KIND").unwrap_or(""))?,
+           _                   => parse_tags_kinds("all")?
+       };
 
-           // Override defaults with file config
-           if let Some(file_config) = ConfigFromFile::load()? {
-               if let Some(fcvt) = file_config.vi_tags { vt = fcvt; }
-               if let Some(fcet) = file_config.emacs_tags { et = fcet; }
-               cte = file_config.ctags_exe;
-               if let Some(fccto) = file_config.ctags_options { cto = fccto; }
+       let output = match matches.value_of("output") {
+           Some("-") => Some(TagsOutput::Stdout),
+           Some(cltf) if Path::new(cltf).components().count() > 1 || Path::new(cltf).is_absolute() => {
+               Some(TagsOutput::File(env::current_dir()?.join(cltf)))
            }
 
-           // Override defaults with commandline options
-           if let Some(cltf) = matches.value_of("output") {
-               match kind {
-                   TagsKind::Vi    => vt = cltf.to_string(),
-                   TagsKind::Emacs => et = cltf.to_string()
-               }
+           _ => None
+       };
+
+       if let Some(TagsOutput::File(ref path)) = output {
+           if ! path.parent().map(Path::is_dir).unwrap_or(false) {
+               return Err(format!("Invalid directory of the path given to '--output': '{}'!", path.display()).into());
            }
+       }
 
-           (vt, et, cte, cto)
-       };
+       if matches.value_of("output").is_some() && kinds.len() > 1 {
+           return Err("The option '--output' is only supported for one kind of tags!".into());
+       }
+
+       // the defaults are overridden by the file config and then by the commandline options
+       let mut options = Options::default();
+       if let Some(file_config) = ConfigFromFile::load()? {
+           options.override_by_file(file_config)?;
+       }
+
+       options.override_by_args(&matches, &kinds, &output)?;
+
+       if options.tags_list && output == Some(TagsOutput::Stdout) && kinds.contains(&TagsKind::Vi) {
+           return Err("The option '--tags-list' isn't supported for vi tags written to stdout!".into());
+       }
+
+       let packages: Vec<String> = matches.values_of("package")
+           .map(|specs| specs.map(String::from).collect())
+           .unwrap_or_default();
 
+       let exclude_packages: Vec<String> = matches.values_of("exclude")
+           .map(|specs| specs.map(String::from).collect())
+           .unwrap_or_default();
+
+       let all_members = matches.is_present("workspace");
        let omit_deps = matches.is_present("omit-deps");
        let force_recreate = matches.is_present("force-recreate");
-       let quiet = matches.is_present("quiet");
-       let verbose = if quiet { false } else { matches.is_present("verbose") };
+       let lock_wait = match matches.value_of("wait") {
+           Some(secs) => {
+               let secs = secs.parse::<u64>()
+                   .map_err(|_| format!("Invalid number of seconds given to '--wait': '{}'!", secs))?;
+
+               LockWait::Timeout(Duration::from_secs(secs))
+           }
+
+           None if matches.is_present("wait") => LockWait::Forever,
+           None                               => LockWait::NoWait
+       };
+       // the tags are written to stdout, so nothing else should be written to it
+       let quiet = matches.is_present("quiet") || is_subcmd_present("quiet") || output == Some(TagsOutput::Stdout);
+       let verbose = if quiet { false } else { matches.is_present("verbose") || is_subcmd_present("verbose") };
 
        let num_threads = if verbose {
            println!("Switching to single threaded for verbose output");
@@ -116,28 +358,131 @@ impl Config {
        };
 
        if verbose {
-           println!("Using configuration: vi_tags='{}', emacs_tags='{}', ctags_exe='{:?}', ctags_options='{}'",
-                    vi_tags, emacs_tags, ctags_exe, ctags_options);
+           println!("Using configuration: {:?}", options);
        }
 
-       let ctags_exe = detect_tags_exe(&ctags_exe)?;
-       if verbose {
-           println!("Found ctags executable: {:?}", ctags_exe);
+       // ctags is only needed - and probed - for commands creating tags
+       let ctags = if command == Command::UpdateTags {
+           let (ctags_exe, ctags_caps) = probe::detect_tags_exe(&options.ctags_exe)?;
+           if verbose {
+               println!("Found ctags executable: {:?}\n   with capabilities: {}", ctags_exe, ctags_caps);
+           }
+
+           Some((ctags_exe, ctags_caps))
+       } else {
+           None
+       };
+
+       let mut tags_spec = TagsSpec::new(kinds, ctags, options.vi_tags, options.emacs_tags, options.ctags_options)?;
+       if command == Command::UpdateTags {
+           tags_spec.check_exe_caps()?;
+           for (_, languages) in &mut options.extra_languages {
+               for language in languages.iter_mut() {
+                   *language = tags_spec.supported_language(language)?;
+               }
+           }
        }
 
+       let target_cfg = if options.eval_cfg && command == Command::UpdateTags {
+           let target_cfg = TargetCfg::detect(&start_dir, options.target.as_deref())?;
+           if verbose {
+               println!("Found cfg options of the target '{}': {:?}",
+                        options.target.as_deref().unwrap_or("host"), target_cfg.option_strings());
+           }
+
+           Some(target_cfg)
+       } else {
+           None
+       };
+
+       // the tags of excluded code are found by the line numbers of the tags
+       tags_spec.filters_code = options.exclude_test_code || options.exclude_root_test_code || target_cfg.is_some();
+
        Ok(Config {
-           tags_spec: TagsSpec::new(kind, ctags_exe, vi_tags, emacs_tags, ctags_options)?,
-           start_dir: start_dir,
-           output_dir_std: output_dir_std,
-           omit_deps: omit_deps,
-           force_recreate: force_recreate,
-           verbose: verbose,
-           quiet: quiet,
-           num_threads: num_threads,
-           temp_dir: TempDir::new()?
+           command,
+           tags_spec,
+           start_dir,
+           output_dir_std,
+           output,
+           packages,
+           exclude_packages,
+           all_members,
+           omit_deps,
+           force_recreate,
+           lock_wait,
+           // a timeout of 0 seconds disables the timeout
+           ctags_timeout: options.ctags_timeout.filter(|secs| *secs > 0).map(Duration::from_secs),
+           keep_going: options.keep_going,
+           inline_includes: options.inline_includes,
+           mirror_deps: options.mirror_deps,
+           tags_list: options.tags_list,
+           workspace_tags: options.workspace_tags,
+           include_std: options.include_std,
+           rustc_dev: options.rustc_dev,
+           out_dir_tags: options.out_dir_tags,
+           extra_languages: options.extra_languages,
+           root_targets: options.root_targets,
+           dep_targets: options.dep_targets,
+           exclude_paths: options.exclude_paths,
+           crate_exclude_paths: options.crate_exclude_paths,
+           gitignore: options.gitignore,
+           exclude_test_code: options.exclude_test_code,
+           exclude_root_test_code: options.exclude_root_test_code,
+           target_cfg,
+           verbose,
+           quiet,
+           num_threads,
+           temp_dir: {
+               let temp_dir = TempDir::new()?;
+               cleanup::register_temp_dir(temp_dir.path());
+               temp_dir
+           }
        })
    }
 
+   /// The key of everything influencing the contents of cached tags of `kind`,
+   /// `gitignore` is if the '.gitignore' files are respected for the source.
+   pub fn tags_key(&self, kind: TagsKind, gitignore: bool) -> TagsKey {
+       TagsKey {
+           rusty_tags_version: crate_version!().to_string(),
+           // the commands not creating tags don't know the ctags executable and don't use the key for caching
+           ctags_exe: self.tags_spec.exe_name().unwrap_or_default().to_string(),
+           ctags_version: self.tags_spec.exe_version().unwrap_or_default().to_string(),
+           ctags_options: self.tags_spec.ctags_options().to_string(),
+           kind: kind.file_extension().to_string(),
+           line_numbers: kind == TagsKind::Vi && self.tags_spec.needs_line_numbers(),
+           inline_includes: kind == TagsKind::Emacs && self.inline_includes,
+           tags_list: self.tags_list,
+           gitignore,
+           exclude_test_code: self.exclude_test_code,
+           target_cfg: self.target_cfg.as_ref().map(TargetCfg::option_strings).unwrap_or_default()
+       }
+   }
+
+   /// the languages - beside of Rust - tagged for the source `name`
+   pub fn extra_languages(&self, name: &str) -> Vec<String> {
+       let mut languages: Vec<String> = self.extra_languages.iter()
+           .filter(|(pattern, _)| glob::matches(pattern, name))
+           .flat_map(|(_, languages)| languages.iter().cloned())
+           .collect();
+
+       languages.sort();
+       languages.dedup();
+       languages
+   }
+
+   /// the exclude patterns of the source `name`, the global and the crate specific ones
+   pub fn exclude_paths(&self, name: &str) -> Vec<String> {
+       let mut patterns = self.exclude_paths.clone();
+       for (pattern, paths) in &self.crate_exclude_paths {
+           if glob::matches(pattern, name) {
+               patterns.extend(paths.iter().cloned());
+           }
+       }
+
+       patterns
+   }
+
    pub fn temp_file(&self, name: &str) -> RtResult<PathBuf> {
        let file_path = self.temp_dir.path().join(name);
        let _ = File::create(&file_path)?;
@@ -145,6 +490,262 @@ impl Config {
    }
 }
 
+#[cfg(test)]
+impl Config {
+   /// The configuration of creating vi tags for the cargo project at `start_dir`
+   /// with the default options, without probing a ctags executable.
+   pub fn for_tests(start_dir: &Path) -> Config {
+       let options = Options::default();
+       let tags_spec = TagsSpec::new(vec![TagsKind::Vi], None, options.vi_tags, options.emacs_tags, options.ctags_options).unwrap();
+
+       Config {
+           command: Command::UpdateTags,
+           tags_spec,
+           start_dir: start_dir.to_path_buf(),
+           output_dir_std: None,
+           output: None,
+           packages: Vec::new(),
+           exclude_packages: Vec::new(),
+           all_members: false,
+           omit_deps: false,
+           force_recreate: false,
+           lock_wait: LockWait::NoWait,
+           ctags_timeout: None,
+           keep_going: options.keep_going,
+           inline_includes: options.inline_includes,
+           mirror_deps: options.mirror_deps,
+           tags_list: options.tags_list,
+           workspace_tags: options.workspace_tags,
+           include_std: options.include_std,
+           rustc_dev: options.rustc_dev,
+           out_dir_tags: options.out_dir_tags,
+           extra_languages: options.extra_languages,
+           root_targets: options.root_targets,
+           dep_targets: options.dep_targets,
+           exclude_paths: options.exclude_paths,
+           crate_exclude_paths: options.crate_exclude_paths,
+           gitignore: options.gitignore,
+           exclude_test_code: options.exclude_test_code,
+           exclude_root_test_code: options.exclude_root_test_code,
+           target_cfg: None,
+           verbose: false,
+           quiet: true,
+           num_threads: 1,
+           temp_dir: TempDir::new().unwrap()
+       }
+   }
+}
+
+/// Parses the extra languages of sources from 'PATTERN=LANGS',
+/// where LANGS is a comma separated list like 'C,C++'.
+fn parse_extra_languages(value: &str) -> RtResult<(String, Vec<String>)> {
+    match value.split_once('=') {
+        Some((pattern, languages)) if ! pattern.trim().is_empty() => {
+            let languages: Vec<String> = languages.split(',')
+                .map(str::trim)
+                .filter(|l| ! l.is_empty())
+                .map(String::from)
+                .collect();
+
+            Ok((pattern.trim().to_string(), languages))
+        }
+
+        _ => Err(format!("Invalid value given to '--extra-languages': '{}', expected e.g. '*-sys=C,C++'!", value).into())
+    }
+}
+
+/// Parses the extra targets from a comma separated list like
+/// 'examples,tests', or 'all' or 'none', given to `option`.
+fn parse_extra_targets(option: &str, kinds_str: &str) -> RtResult<ExtraTargets> {
+    let mut targets = ExtraTargets::default();
+    for kind_str in kinds_str.split(',').map(str::trim).filter(|k| ! k.is_empty()) {
+        match kind_str.to_lowercase().as_str() {
+            "examples" => targets.examples = true,
+            "benches"  => targets.benches = true,
+            "tests"    => targets.tests = true,
+            "all"      => targets = ExtraTargets::all(),
+            "none"     => {}
+            _          => return Err(format!("Invalid target kind '{}' given to '{}', expected one of: examples, benches, tests, all, none",
+                                             kind_str, option).into())
+        }
+    }
+
+    Ok(targets)
+}
+
+/// Parses the kinds of tags from a comma separated list
+/// like 'vi,emacs', or 'all' for all kinds of tags.
+fn parse_tags_kinds(kinds_str: &str) -> RtResult<Vec<TagsKind>> {
+    let mut kinds = Vec::new();
+    for kind_str in kinds_str.split(',').map(str::trim) {
+        if kind_str.eq_ignore_ascii_case("all") {
+            for kind in &TagsKind::variants() {
+                kinds.push(kind.parse::<TagsKind>()?);
+            }
+
+            continue;
+        }
+
+        kinds.push(kind_str.parse::<TagsKind>()
+            .map_err(|_| format!("Invalid tags kind '{}', expected one of: vi, emacs, all", kind_str))?);
+    }
+
+    let mut unique_kinds = Vec::with_capacity(kinds.len());
+    for kind in kinds {
+        if ! unique_kinds.contains(&kind) {
+            unique_kinds.push(kind);
+        }
+    }
+
+    Ok(unique_kinds)
+}
+
+/// The options given by the configuration file and on the commandline.
+#[derive(Debug)]
+struct Options {
+    vi_tags: String,
+    emacs_tags: String,
+    ctags_exe: Option<String>,
+    ctags_options: String,
+    ctags_timeout: Option<u64>,
+    keep_going: bool,
+    inline_includes: bool,
+    mirror_deps: bool,
+    tags_list: bool,
+    workspace_tags: bool,
+    include_std: bool,
+    rustc_dev: bool,
+    out_dir_tags: bool,
+    extra_languages: Vec<(String, Vec<String>)>,
+    root_targets: ExtraTargets,
+    dep_targets: ExtraTargets,
+    exclude_paths: Vec<String>,
+    crate_exclude_paths: Vec<(String, Vec<String>)>,
+    gitignore: bool,
+    exclude_test_code: bool,
+    exclude_root_test_code: bool,
+    eval_cfg: bool,
+    target: Option<String>
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            vi_tags: "rusty-tags.vi".to_string(),
+            emacs_tags: "rusty-tags.emacs".to_string(),
+            ctags_exe: None,
+            ctags_options: "".to_string(),
+            ctags_timeout: None,
+            keep_going: false,
+            inline_includes: false,
+            mirror_deps: false,
+            tags_list: false,
+            workspace_tags: false,
+            include_std: false,
+            rustc_dev: false,
+            out_dir_tags: false,
+            extra_languages: Vec::new(),
+            root_targets: ExtraTargets::all(),
+            dep_targets: ExtraTargets::default(),
+            exclude_paths: Vec::new(),
+            crate_exclude_paths: Vec::new(),
+            gitignore: false,
+            exclude_test_code: false,
+            exclude_root_test_code: false,
+            eval_cfg: false,
+            target: None
+        }
+    }
+}
+
+impl Options {
+    /// Overrides the options by the ones set in the configuration file.
+    fn override_by_file(&mut self, file_config: ConfigFromFile) -> RtResult<()> {
+        if let Some(fcvt) = file_config.vi_tags { self.vi_tags = fcvt; }
+        if let Some(fcet) = file_config.emacs_tags { self.emacs_tags = fcet; }
+        self.ctags_exe = file_config.ctags_exe;
+        if let Some(fccto) = file_config.ctags_options { self.ctags_options = fccto; }
+        if let Some(fcctt) = file_config.ctags_timeout { self.ctags_timeout = Some(fcctt); }
+        if let Some(fckg) = file_config.keep_going { self.keep_going = fckg; }
+        if let Some(fcii) = file_config.inline_includes { self.inline_includes = fcii; }
+        if let Some(fcmd) = file_config.mirror_deps { self.mirror_deps = fcmd; }
+        if let Some(fctl) = file_config.tags_list { self.tags_list = fctl; }
+        if let Some(fcwt) = file_config.workspace_tags { self.workspace_tags = fcwt; }
+        if let Some(fcis) = file_config.include_std { self.include_std = fcis; }
+        if let Some(fcrd) = file_config.rustc_dev { self.rustc_dev = fcrd; }
+        if let Some(fcodt) = file_config.out_dir_tags { self.out_dir_tags = fcodt; }
+        if let Some(fcel) = file_config.extra_languages { self.extra_languages.extend(fcel); }
+        if let Some(fcrt) = file_config.root_targets { self.root_targets = parse_extra_targets("root_targets", &fcrt)?; }
+        if let Some(fcdt) = file_config.dep_targets { self.dep_targets = parse_extra_targets("dep_targets", &fcdt)?; }
+        if let Some(fcep) = file_config.exclude_paths { self.exclude_paths = fcep; }
+        if let Some(fccep) = file_config.crate_exclude_paths { self.crate_exclude_paths.extend(fccep); }
+        if let Some(fcgi) = file_config.gitignore { self.gitignore = fcgi; }
+        if let Some(fcetc) = file_config.exclude_test_code { self.exclude_test_code = fcetc; }
+        if let Some(fcertc) = file_config.exclude_root_test_code { self.exclude_root_test_code = fcertc; }
+        if let Some(fcec) = file_config.eval_cfg { self.eval_cfg = fcec; }
+        if let Some(fctg) = file_config.target { self.target = Some(fctg).filter(|t| ! t.is_empty()); }
+        Ok(())
+    }
+
+    /// Overrides the options by the ones given on the commandline.
+    fn override_by_args(&mut self, matches: &ArgMatches, kinds: &[TagsKind], output: &Option<TagsOutput>) -> RtResult<()> {
+        if let (Some(cltf), None) = (matches.value_of("output"), output) {
+            match kinds[0] {
+                TagsKind::Vi    => self.vi_tags = cltf.to_string(),
+                TagsKind::Emacs => self.emacs_tags = cltf.to_string()
+            }
+        }
+
+        if let Some(secs) = matches.value_of("ctags-timeout") {
+            self.ctags_timeout = Some(secs.parse::<u64>()
+                .map_err(|_| format!("Invalid number of seconds given to '--ctags-timeout': '{}'!", secs))?);
+        }
+
+        self.keep_going |= matches.is_present("keep-going");
+        self.inline_includes |= matches.is_present("inline-includes");
+        self.mirror_deps |= matches.is_present("mirror-deps");
+        self.tags_list |= matches.is_present("tags-list");
+        self.workspace_tags |= matches.is_present("workspace-tags");
+        self.include_std |= matches.is_present("include-std");
+        self.rustc_dev |= matches.is_present("rustc-dev");
+        self.out_dir_tags |= matches.is_present("out-dir-tags");
+
+        if let Some(kinds) = matches.value_of("root-targets") {
+            self.root_targets = parse_extra_targets("--root-targets", kinds)?;
+        }
+
+        if let Some(kinds) = matches.value_of("dep-targets") {
+            self.dep_targets = parse_extra_targets("--dep-targets", kinds)?;
+        }
+
+        if let Some(globs) = matches.values_of("exclude-path") {
+            self.exclude_paths.extend(globs.map(String::from));
+        }
+
+        self.gitignore |= matches.is_present("gitignore");
+        self.exclude_test_code |= matches.is_present("exclude-test-code");
+        self.exclude_root_test_code |= matches.is_present("exclude-root-test-code");
+        self.eval_cfg |= matches.is_present("eval-cfg");
+
+        if let Some(triple) = matches.value_of("target") {
+            self.target = Some(triple.to_string());
+        }
+
+        // a target is only needed for the evaluation of the cfg predicates
+        if self.target.is_some() {
+            self.eval_cfg = true;
+        }
+
+        if let Some(values) = matches.values_of("extra-languages") {
+            for value in values {
+                self.extra_languages.push(parse_extra_languages(value)?);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// Represents the data from a `.rusty-tags/config.toml` configuration file.
 #[derive(Deserialize, Debug, Default)]
 struct ConfigFromFile {
@@ -158,7 +759,66 @@ struct ConfigFromFile {
     ctags_exe: Option<String>,
 
     /// options given to the ctags executable
-    ctags_options: Option<String>
+    ctags_options: Option<String>,
+
+    /// the maximal duration of a ctags run in seconds
+    ctags_timeout: Option<u64>,
+
+    /// continue with the other sources if ctags fails for a source
+    keep_going: Option<bool>,
+
+    /// inline the tags of dependencies into emacs tags files
+    inline_includes: Option<bool>,
+
+    /// write the tags files of dependencies into the mirror directory
+    mirror_deps: Option<bool>,
+
+    /// only create the tags of each source and lists of tags files
+    tags_list: Option<bool>,
+
+    /// create an aggregate tags file at the workspace root
+    workspace_tags: Option<bool>,
+
+    /// include the tags of the standard library into the tags of the roots
+    include_std: Option<bool>,
+
+    /// include the tags of the compiler crates into the tags of the roots
+    rustc_dev: Option<bool>,
+
+    /// create the tags of the code generated by build scripts into 'OUT_DIR'
+    out_dir_tags: Option<bool>,
+
+    /// the languages - beside of Rust - tagged for the sources
+    /// whose names match the glob patterns
+    extra_languages: Option<BTreeMap<String, Vec<String>>>,
+
+    /// the targets tagged for the roots beside of libraries and binaries
+    root_targets: Option<String>,
+
+    /// the targets tagged for the dependencies beside of libraries and binaries
+    dep_targets: Option<String>,
+
+    /// the files and directories excluded from the tags of all sources
+    exclude_paths: Option<Vec<String>>,
+
+    /// the files and directories excluded from the tags of
+    /// the sources whose names match the glob patterns
+    crate_exclude_paths: Option<BTreeMap<String, Vec<String>>>,
+
+    /// respect the '.gitignore' and '.ignore' files of roots and path sources
+    gitignore: Option<bool>,
+
+    /// exclude the tags of the test code of the dependencies
+    exclude_test_code: Option<bool>,
+
+    /// exclude the tags of the test code of the roots
+    exclude_root_test_code: Option<bool>,
+
+    /// exclude the tags of the items whose '#[cfg]' predicates are false
+    eval_cfg: Option<bool>,
+
+    /// the target whose cfg options are used for the evaluation, implies 'eval_cfg'
+    target: Option<String>
 }
 
 impl ConfigFromFile {
@@ -191,27 +851,80 @@ fn map_file<R, F>(file: &Path, f: F) -> RtResult<R>
     Ok(r)
 }
 
-fn detect_tags_exe(ctags_exe: &Option<String>) -> RtResult<TagsExe> {
-    let exes = match *ctags_exe {
-        Some(ref exe) if exe != "" => vec![exe.as_str()],
-        _                          => vec!["ctags", "exuberant-ctags", "exctags", "universal-ctags", "uctags"]
-    };
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use types::TagsExe;
+    use probe::TagsCapabilities;
+    use super::*;
 
-    for exe in &exes {
-        let mut cmd = Command::new(exe);
-        cmd.arg("--version");
+    #[test]
+    fn parse_kinds() {
+        assert_eq!(parse_tags_kinds("vi").unwrap(), vec![TagsKind::Vi]);
+        assert_eq!(parse_tags_kinds("emacs, vi").unwrap(), vec![TagsKind::Emacs, TagsKind::Vi]);
+        assert_eq!(parse_tags_kinds("all").unwrap(), vec![TagsKind::Vi, TagsKind::Emacs]);
 
-        if let Ok(output) = cmd.output() {
-            if output.status.success() {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                if stdout.contains("Universal Ctags") {
-                    return Ok(TagsExe::UniversalCtags(exe.to_string()));
-                }
+        // each kind is only created once
+        assert_eq!(parse_tags_kinds("vi,all,emacs").unwrap(), vec![TagsKind::Vi, TagsKind::Emacs]);
 
-                return Ok(TagsExe::ExuberantCtags(exe.to_string()));
-            }
-        }
+        assert!(parse_tags_kinds("").is_err());
+        assert!(parse_tags_kinds("vi,ctags").is_err());
+    }
+
+    #[test]
+    fn same_tags_names_for_both_kinds() {
+        let spec = |vi_tags: &str, emacs_tags: &str| {
+            TagsSpec::new(vec![TagsKind::Vi, TagsKind::Emacs], None, vi_tags.to_string(), emacs_tags.to_string(), String::new())
+        };
+
+        assert!(spec("tags", "TAGS").is_ok());
+        assert!(spec("tags", "tags").is_err());
+    }
+
+    #[test]
+    fn extra_languages_of_crates() {
+        assert_eq!(parse_extra_languages("*-sys = C, C++,").unwrap(), ("*-sys".to_string(), vec!["C".to_string(), "C++".to_string()]));
+        assert!(parse_extra_languages("=C").is_err());
+        assert!(parse_extra_languages("*-sys").is_err());
+
+        let mut config = Config::for_tests(Path::new("."));
+        config.extra_languages = vec![parse_extra_languages("*-sys=C++,C").unwrap(), parse_extra_languages("openssl-*=C,Asm").unwrap()];
+        assert_eq!(config.extra_languages("openssl-sys"), vec!["Asm", "C", "C++"]);
+        assert_eq!(config.extra_languages("libgit2-sys"), vec!["C", "C++"]);
+        assert!(config.extra_languages("openssl").is_empty());
     }
 
-    Err(format!("Couldn't find 'ctags' executable! Searched for executables with names: {:?}. Is 'ctags' correctly installed?", &exes).into())
+    #[test]
+    fn supported_extra_languages() {
+        let caps = TagsCapabilities {
+            rust_parser: true,
+            languages: vec!["Asm".to_string(), "C".to_string(), "C++".to_string(), "Rust".to_string()],
+            language_maps: vec![("C".to_string(), vec!["*.c".to_string()]),
+                                ("C++".to_string(), vec!["*.cpp".to_string(), "*.h".to_string()]),
+                                ("Rust".to_string(), vec!["*.rs".to_string()])],
+            ..TagsCapabilities::default()
+        };
+
+        let spec = TagsSpec::new(vec![TagsKind::Vi], Some((TagsExe::UniversalCtags("ctags".to_string()), caps)),
+                                 "tags".to_string(), "TAGS".to_string(), String::new()).unwrap();
+
+        assert_eq!(spec.supported_language("c++").unwrap(), "C++");
+        assert!(spec.supported_language("Fortran").is_err());
+
+        assert_eq!(spec.file_patterns(&["C".to_string(), "C++".to_string()]).unwrap(), vec!["*.rs", "*.c", "*.cpp", "*.h"]);
+
+        // the patterns of the assembler aren't known
+        assert!(spec.file_patterns(&["Asm".to_string()]).is_none());
+    }
+
+    #[test]
+    fn parse_targets() {
+        assert_eq!(parse_extra_targets("--dep-targets", "").unwrap(), ExtraTargets::default());
+        assert_eq!(parse_extra_targets("--dep-targets", "none").unwrap(), ExtraTargets::default());
+        assert_eq!(parse_extra_targets("--dep-targets", "all").unwrap(), ExtraTargets::all());
+        assert_eq!(parse_extra_targets("--root-targets", "Examples, tests").unwrap(),
+                   ExtraTargets { examples: true, benches: false, tests: true });
+
+        assert!(parse_extra_targets("--root-targets", "examples,bins").is_err());
+    }
 }
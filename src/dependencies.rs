@@ -1,11 +1,16 @@
-use std::path::Path;
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use semver::Version;
 use fnv::FnvHashMap;
 
 use rt_result::RtResult;
-use types::{DepTree, Source, SourceId};
+use types::{DepTree, Source, SourceId, SourceKind};
 use config::Config;
+use std_lib;
+use cleanup;
 
 type JsonValue = serde_json::Value;
 type JsonObject = serde_json::Map<String, JsonValue>;
@@ -14,8 +19,8 @@ type JsonObject = serde_json::Map<String, JsonValue>;
 pub fn dependency_tree(config: &Config, metadata: &JsonValue) -> RtResult<DepTree> {
     let mut dep_tree = DepTree::new();
     let packages = packages(config, metadata, &mut dep_tree)?;
-
-    build_dep_tree(config, metadata, &packages, &mut dep_tree)?;
+    let out_dirs = OutDirs::new(config, metadata)?;
+    build_dep_tree(config, metadata, &packages, &out_dirs, &mut dep_tree)?;
     dep_tree.compute_depths();
 
     Ok(dep_tree)
@@ -37,10 +42,21 @@ fn workspace_members<'a>(metadata: &'a JsonValue) -> RtResult<Vec<PackageId<'a>>
 type PackageId<'a> = &'a str;
 
 struct Package<'a> {
+    pub id: PackageId<'a>,
     pub name: &'a str,
     pub version: Version,
     pub source_id: SourceId,
-    pub source_path: &'a Path
+    pub source_path: &'a Path,
+    /// the directories of all targets with their kinds
+    pub target_dirs: Vec<(&'a str, &'a Path)>,
+    /// the root source files of the library and binary targets
+    pub crate_roots: Vec<&'a Path>,
+    pub manifest_dir: &'a Path,
+    pub kind: SourceKind,
+    pub is_proc_macro: bool,
+    pub has_build_script: bool,
+    /// the features enabled by the resolve of cargo
+    pub features: Option<Vec<String>>
 }
 
 type Packages<'a> = FnvHashMap<PackageId<'a>, Package<'a>>;
@@ -51,24 +67,32 @@ fn packages<'a>(config: &Config,
                 -> RtResult<Packages<'a>> {
     let packages = as_array_from_value("packages", metadata)?;
     dep_tree.reserve_num_sources(packages.len());
+    let mut features = enabled_features(metadata)?;
     let mut package_map = FnvHashMap::default();
     for package in packages {
         let id = as_str_from_value("id", package)?;
         let name = as_str_from_value("name", package)?;
         let version = Version::parse(as_str_from_value("version", package)?)?;
-        let source_path = {
-            let path = source_path(config, package)?;
-            if path == None {
-                continue;
-            }
+        let target_dirs = target_dirs(config, package)?;
 
-            path.unwrap()
+        // the source path is the directory of the first library or binary target,
+        // packages without one - e.g. only containing examples - are ignored
+        let source_path = match target_dirs.iter().find(|(kind, _)| ! is_extra_target_kind(kind)) {
+            Some((_, path)) => *path,
+            None            => continue
         };
 
+        let crate_roots = crate_roots(package)?;
+        let manifest_dir = manifest_dir(package)?;
+        let kind = SourceKind::from_metadata(package.get("source").and_then(JsonValue::as_str));
+        let is_proc_macro = has_target_kind(package, "proc-macro")?;
+        let has_build_script = has_target_kind(package, "custom-build")?;
+        let features = features.remove(id);
+
         verbose!(config, "Found package of {} {} with source at '{}'", name, version, source_path.display());
 
         let source_id = dep_tree.new_source();
-        package_map.insert(id, Package { name, version, source_id, source_path });
+        package_map.insert(id, Package { id, name, version, source_id, source_path, target_dirs, crate_roots, manifest_dir, kind, is_proc_macro, has_build_script, features });
     }
 
     Ok(package_map)
@@ -77,28 +101,45 @@ fn packages<'a>(config: &Config,
 fn build_dep_tree(config: &Config,
                   metadata: &JsonValue,
                   packages: &Packages,
+                  out_dirs: &OutDirs,
                   dep_tree: &mut DepTree)
                   -> RtResult<()> {
-    let root_ids = {
-        let members_ids = workspace_members(metadata)?;
-        verbose!(config, "Found workspace members: {:?}", members_ids);
-
-        let mut source_ids = Vec::with_capacity(members_ids.len());
-        for member_id in &members_ids {
-            let member_package = package(&member_id, packages)?;
-            source_ids.push(member_package.source_id);
-            if config.omit_deps {
-                let is_root = true;
-                let source = Source::new(member_package.source_id, member_package.name, &member_package.version,
-                                         member_package.source_path, is_root, config)?;
-                dep_tree.set_source(source, vec![]);
-            }
+    let members_ids = workspace_members(metadata)?;
+    verbose!(config, "Found workspace members: {:?}", members_ids);
+
+    let workspace_root = metadata.get("workspace_root").and_then(JsonValue::as_str).map(PathBuf::from);
+
+    // the workspace tags of multiple members are written at the workspace
+    // root, where the tags of a member might already be written
+    let workspace_tags_dir = match workspace_root {
+        Some(ref root) if config.workspace_tags && config.output.is_none() && members_ids.len() > 1 => Some(root.as_path()),
+        _ => None
+    };
+
+    let mut members = Vec::with_capacity(members_ids.len());
+    for member_id in &members_ids {
+        let member_package = package(member_id, packages)?;
+        members.push(member_package);
+        if config.omit_deps {
+            let is_root = true;
+            let source = new_source(config, member_package, is_root, out_dirs, workspace_tags_dir)?;
+            dep_tree.set_source(source, vec![]);
         }
+    }
 
-        source_ids
+    let member_ids: Vec<_> = members.iter().map(|m| m.source_id).collect();
+    let root_ids: Vec<_> = {
+        let selected_members = select_members(config, &members)?;
+        verbose!(config, "Selected workspace members: {:?}", selected_members.iter().map(|m| m.name).collect::<Vec<_>>());
+        selected_members.iter().map(|m| m.source_id).collect()
     };
 
-    dep_tree.set_roots(root_ids.clone());
+    dep_tree.set_roots(root_ids);
+    dep_tree.set_members(member_ids.clone());
+    if let Some(ref workspace_root) = workspace_root {
+        dep_tree.set_workspace_root(workspace_root.clone());
+    }
+
     if config.omit_deps {
         return Ok(());
     }
@@ -140,30 +181,246 @@ fn build_dep_tree(config: &Config,
 
         verbose!(config, "Building tree for {} {}", node_package.name, node_package.version);
 
-        let is_root = root_ids.iter().find(|id| **id == node_package.source_id) != None;
-        let source = Source::new(node_package.source_id, node_package.name, &node_package.version,
-                                 node_package.source_path, is_root, config)?;
+        let is_root = member_ids.contains(&node_package.source_id);
+        let source = new_source(config, node_package, is_root, out_dirs, workspace_tags_dir)?;
         dep_tree.set_source(source, dep_src_ids);
     }
 
     Ok(())
 }
 
+/// Creates the source of `package`, the tags of a root aren't written into the
+/// tags files of the workspace tags at `workspace_tags_dir`.
+fn new_source(config: &Config, package: &Package, is_root: bool, out_dirs: &OutDirs, workspace_tags_dir: Option<&Path>) -> RtResult<Source> {
+    // the sources of other languages - e.g. the bundled C library of a '-sys'
+    // crate - are mostly outside of the Rust source directory
+    let source_path = if config.extra_languages(package.name).is_empty() {
+        package.source_path
+    } else {
+        package.manifest_dir
+    };
+
+    let mut source = Source::new(package.source_id, package.name, &package.version,
+                                 source_path, package.kind.clone(), is_root, config)?;
+    source.is_proc_macro = package.is_proc_macro;
+    if let (true, Some(dir)) = (is_root, workspace_tags_dir) {
+        source.set_member_tags_files(dir, config);
+    }
+
+    if config.rustc_dev && is_root {
+        source.uses_rustc_private = std_lib::uses_rustc_private(&package.crate_roots);
+    }
+
+    if config.target_cfg.is_some() {
+        source.features = package.features.clone();
+    }
+
+    let extra_targets = if is_root { config.root_targets } else { config.dep_targets };
+    let target_dirs = package.target_dirs.iter()
+        .filter(|(kind, _)| extra_targets.contains_kind(kind))
+        .map(|(_, dir)| dir.to_path_buf())
+        .collect();
+
+    source.set_target_dirs(target_dirs);
+    if config.out_dir_tags && package.has_build_script {
+        match out_dirs.get(config, package)? {
+            Some(out_dir) => {
+                verbose!(config, "Found OUT_DIR of {} {} at '{}'", package.name, package.version, out_dir.display());
+                source.set_out_dir(out_dir);
+            }
+
+            None => verbose!(config, "Couldn't find OUT_DIR of {} {}, did its build script fail?", package.name, package.version)
+        }
+    }
+
+    Ok(source)
+}
+
+/// The 'OUT_DIR's of the build scripts of the packages.
+struct OutDirs {
+    /// the directory of the build scripts run for the 'dev' profile,
+    /// 'None' if the code generated by build scripts isn't tagged
+    build_dir: Option<PathBuf>,
+
+    /// the 'OUT_DIR's by package id reported by 'cargo check', which
+    /// is only run if the 'OUT_DIR' of a package is missing
+    checked: RefCell<Option<FnvHashMap<String, PathBuf>>>
+}
+
+impl OutDirs {
+    fn new(config: &Config, metadata: &JsonValue) -> RtResult<OutDirs> {
+        let build_dir = if config.out_dir_tags {
+            Some(Path::new(as_str_from_value("target_directory", metadata)?).join("debug").join("build"))
+        } else {
+            None
+        };
+
+        Ok(OutDirs { build_dir, checked: RefCell::new(None) })
+    }
+
+    /// The 'OUT_DIR' of the build script of `package`. The one of an already run build
+    /// script - e.g. by 'cargo build' - is reused, otherwise the build scripts are run.
+    fn get(&self, config: &Config, package: &Package) -> RtResult<Option<PathBuf>> {
+        let build_dir = match self.build_dir {
+            Some(ref build_dir) => build_dir,
+            None                => return Ok(None)
+        };
+
+        if let Some(out_dir) = existing_out_dir(build_dir, package.name) {
+            return Ok(Some(out_dir));
+        }
+
+        let mut checked = self.checked.borrow_mut();
+        if checked.is_none() {
+            *checked = Some(run_build_scripts(config)?);
+        }
+
+        Ok(checked.as_ref().and_then(|out_dirs| out_dirs.get(package.id)).cloned())
+    }
+}
+
+/// The 'OUT_DIR' of the build script of the package `name` inside of `build_dir`, the build
+/// script directories are named '{name}-{hash}'. 'None' if there's none or if there're
+/// several - e.g. of multiple versions of the package - which can't be told apart.
+fn existing_out_dir(build_dir: &Path, name: &str) -> Option<PathBuf> {
+    let prefix = format!("{}-", name);
+    let mut out_dirs = fs::read_dir(build_dir).ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|dir| {
+            dir.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(&prefix))
+                .map(|hash| ! hash.is_empty() && hash.chars().all(|c| c.is_ascii_hexdigit()))
+                .unwrap_or(false)
+        })
+        // only the directory of the executed build script contains its output
+        .filter(|dir| dir.join("output").is_file() && dir.join("out").is_dir())
+        .map(|dir| dir.join("out"));
+
+    match (out_dirs.next(), out_dirs.next()) {
+        (Some(out_dir), None) => Some(out_dir),
+        _                     => None
+    }
+}
+
+/// Gets the 'OUT_DIR's of the build scripts from the 'build-script-executed' messages
+/// of 'cargo check', which runs the build scripts if they aren't up to date. The
+/// messages are also read if the check fails, e.g. because of a compile error of a
+/// workspace member, then the 'OUT_DIR's of the executed build scripts are known.
+fn run_build_scripts(config: &Config) -> RtResult<FnvHashMap<String, PathBuf>> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("check")
+       .arg("--workspace")
+       .arg("--all-targets")
+       .arg("--message-format=json")
+       .current_dir(&config.start_dir);
+
+    verbose!(config, "Running the build scripts with: {:?}", cmd);
+    let output = cleanup::run(&mut cmd)
+        .map_err(|err| format!("'cargo' execution failed: {}\nIs 'cargo' correctly installed?", err))?;
+
+    if ! output.status.success() {
+        verbose!(config, "'cargo check' failed, only the OUT_DIRs of the executed build scripts are known:\n{}",
+                 String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let mut out_dirs = FnvHashMap::default();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let message: JsonValue = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_)      => continue
+        };
+
+        if message.get("reason").and_then(JsonValue::as_str) != Some("build-script-executed") {
+            continue;
+        }
+
+        if let (Some(id), Some(out_dir)) = (message.get("package_id").and_then(JsonValue::as_str),
+                                            message.get("out_dir").and_then(JsonValue::as_str)) {
+            out_dirs.insert(id.to_string(), PathBuf::from(out_dir));
+        }
+    }
+
+    Ok(out_dirs)
+}
+
+/// Selects the workspace members for which tags are created. If no
+/// members are given with '--package' and neither '--workspace' nor
+/// '--exclude' are given, then the member containing the start
+/// directory is selected, or all members if there's none.
+fn select_members<'a, 'b>(config: &Config, members: &[&'b Package<'a>]) -> RtResult<Vec<&'b Package<'a>>> {
+    let mut selected = Vec::with_capacity(members.len());
+    if ! config.packages.is_empty() {
+        for spec in &config.packages {
+            let member = members.iter()
+                .find(|m| matches_package_spec(m, spec))
+                .ok_or(format!("Couldn't find workspace member '{}' given to '--package'!", spec))?;
+
+            selected.push(*member);
+        }
+    } else if ! config.all_members && config.exclude_packages.is_empty() {
+        let start_dir = config.start_dir.canonicalize()?;
+        let containing_member = members.iter()
+            .filter(|m| start_dir.starts_with(m.manifest_dir))
+            .max_by_key(|m| m.manifest_dir.components().count());
+
+        if let Some(member) = containing_member {
+            selected.push(*member);
+        }
+    }
+
+    if selected.is_empty() {
+        selected.extend(members.iter());
+    }
+
+    for spec in &config.exclude_packages {
+        if members.iter().find(|m| matches_package_spec(m, spec)).is_none() {
+            return Err(format!("Couldn't find workspace member '{}' given to '--exclude'!", spec).into());
+        }
+
+        selected.retain(|m| ! matches_package_spec(m, spec));
+    }
+
+    if selected.is_empty() {
+        return Err("All workspace members are excluded!".into());
+    }
+
+    Ok(selected)
+}
+
+/// If the package is matched by the package spec, which is either
+/// the package name, or the package name with a version like
+/// 'name@version' or 'name:version'.
+fn matches_package_spec(package: &Package, spec: &str) -> bool {
+    match spec.find(['@', ':']) {
+        Some(idx) => {
+            let (name, version) = (&spec[..idx], &spec[idx + 1..]);
+            package.name == name && package.version.to_string() == version
+        }
+
+        None => package.name == spec
+    }
+}
+
 fn package<'a>(package_id: &PackageId<'a>, packages: &'a Packages) -> RtResult<&'a Package<'a>> {
     packages.get(package_id)
         .ok_or(format!("Couldn't find package for id '{}'", package_id).into())
 }
 
-fn source_path<'a>(config: &Config, package: &'a JsonValue) -> RtResult<Option<&'a Path>> {
-    let targets = as_array_from_value("targets", package)?;
+fn manifest_dir(package: &JsonValue) -> RtResult<&Path> {
+    let manifest_path = as_str_from_value("manifest_path", package).map(Path::new)?;
 
-    let manifest_dir = {
-        let manifest_path = as_str_from_value("manifest_path", package).map(Path::new)?;
+    Ok(manifest_path.parent()
+        .ok_or(format!("Couldn't get directory of path '{:?}'", manifest_path.display()))?)
+}
 
-        manifest_path.parent()
-            .ok_or(format!("Couldn't get directory of path '{:?}'", manifest_path.display()))?
-    };
+/// The source directories of all supported targets of the package - libraries,
+/// binaries, examples, benches and tests - with the kinds of the targets.
+fn target_dirs<'a>(config: &Config, package: &'a JsonValue) -> RtResult<Vec<(&'a str, &'a Path)>> {
+    let targets = as_array_from_value("targets", package)?;
+    let manifest_dir = manifest_dir(package)?;
 
+    let mut target_dirs = Vec::with_capacity(targets.len());
     for target in targets {
         let kinds = as_array_from_value("kind", target)?;
 
@@ -171,7 +428,7 @@ fn source_path<'a>(config: &Config, package: &'a JsonValue) -> RtResult<Option<&
             let kind_str = kind.as_str()
                 .ok_or(format!("Expected 'kind' of type string but found: {}", to_string_pretty(kind)))?;
 
-            if kind_str != "bin" && ! kind_str.contains("lib") && kind_str != "proc-macro" && kind_str != "test" {
+            if kind_str != "bin" && ! kind_str.contains("lib") && kind_str != "proc-macro" && ! is_extra_target_kind(kind_str) {
                 verbose!(config, "Unsupported target kind: {}", kind_str);
                 continue;
             }
@@ -192,11 +449,67 @@ fn source_path<'a>(config: &Config, package: &'a JsonValue) -> RtResult<Option<&
                                    src_path.display(), to_string_pretty(target), to_string_pretty(package)).into());
             }
 
-            return Ok(Some(src_path));
+            target_dirs.push((kind_str, src_path));
+        }
+    }
+
+    Ok(target_dirs)
+}
+
+/// The root source files of the library and binary targets of the package.
+fn crate_roots(package: &JsonValue) -> RtResult<Vec<&Path>> {
+    let mut crate_roots = Vec::new();
+    for target in as_array_from_value("targets", package)? {
+        let is_crate = as_array_from_value("kind", target)?.iter()
+            .filter_map(JsonValue::as_str)
+            .any(|k| k == "bin" || k.contains("lib") || k == "proc-macro");
+
+        if is_crate {
+            crate_roots.push(as_str_from_value("src_path", target).map(Path::new)?);
+        }
+    }
+
+    Ok(crate_roots)
+}
+
+/// if targets of `kind` are only tagged if configured, see 'ExtraTargets'
+fn is_extra_target_kind(kind: &str) -> bool {
+    kind == "example" || kind == "bench" || kind == "test"
+}
+
+/// The enabled features of the packages by package id, from the nodes of the resolve,
+/// which might be missing, e.g. if the metadata was created by an older cargo version.
+fn enabled_features<'a>(metadata: &'a JsonValue) -> RtResult<FnvHashMap<PackageId<'a>, Vec<String>>> {
+    let mut features = FnvHashMap::default();
+    let nodes = match metadata.get("resolve").and_then(|r| r.get("nodes")).and_then(JsonValue::as_array) {
+        Some(nodes) => nodes,
+        None        => return Ok(features)
+    };
+
+    for node in nodes {
+        if let Some(node_features) = node.get("features").and_then(JsonValue::as_array) {
+            let mut node_features: Vec<String> = node_features.iter()
+                .filter_map(JsonValue::as_str)
+                .map(String::from)
+                .collect();
+
+            node_features.sort();
+            features.insert(as_str_from_value("id", node)?, node_features);
+        }
+    }
+
+    Ok(features)
+}
+
+/// if the package has a target of `kind`, e.g. 'proc-macro' or 'custom-build'
+fn has_target_kind(package: &JsonValue, kind: &str) -> RtResult<bool> {
+    for target in as_array_from_value("targets", package)? {
+        if as_array_from_value("kind", target)?.iter().any(|k| k.as_str() == Some(kind)) {
+            return Ok(true);
         }
     }
 
-    Ok(None)
+    Ok(false)
 }
 
 fn to_string_pretty(value: &JsonValue) -> String {
@@ -226,3 +539,173 @@ fn as_array_from_object<'a>(entry: &str, object: &'a JsonObject) -> RtResult<&'a
           .and_then(JsonValue::as_array)
           .ok_or(format!("Couldn't find array entry '{}' in:\n{:?}", entry, object).into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+    use config::Config;
+    use types::{DepTree, TagsKind, TagsOutput};
+    use super::*;
+
+    /// Creates the library packages `names` inside of the workspace at `root` - each
+    /// one with its relative directory - and returns the cargo metadata of the workspace.
+    fn workspace(root: &Path, names: &[(&str, &str)]) -> JsonValue {
+        let mut packages = Vec::new();
+        let mut nodes = Vec::new();
+        for (name, rel_dir) in names {
+            let dir = root.join(rel_dir);
+            fs::create_dir_all(dir.join("src")).unwrap();
+            File::create(dir.join("Cargo.toml")).unwrap();
+            File::create(dir.join("src").join("lib.rs")).unwrap();
+
+            let id = format!("{} 0.1.0 (path+file://{})", name, dir.display());
+            packages.push(serde_json::json!({
+                "id": id,
+                "name": name,
+                "version": "0.1.0",
+                "source": null,
+                "manifest_path": dir.join("Cargo.toml"),
+                "targets": [{ "kind": ["lib"], "src_path": dir.join("src").join("lib.rs") }]
+            }));
+
+            nodes.push(serde_json::json!({ "id": id, "dependencies": [], "features": [] }));
+        }
+
+        let members: Vec<_> = packages.iter().map(|p| p["id"].clone()).collect();
+        serde_json::json!({
+            "packages": packages,
+            "workspace_members": members,
+            "resolve": { "nodes": nodes },
+            "workspace_root": root
+        })
+    }
+
+    fn tags_files(dep_tree: &DepTree) -> Vec<(String, PathBuf)> {
+        let mut tags_files: Vec<_> = dep_tree.members()
+            .map(|m| (m.name.clone(), m.tags_files(TagsKind::Vi).tags_file.clone()))
+            .collect();
+
+        tags_files.sort();
+        tags_files
+    }
+
+    fn root_names(config: &Config, metadata: &JsonValue) -> RtResult<Vec<String>> {
+        let dep_tree = dependency_tree(config, metadata)?;
+        let mut names: Vec<_> = dep_tree.roots().map(|r| r.name.clone()).collect();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn select_members_by_package_exclude_and_start_dir() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let metadata = workspace(root, &[("app", ""), ("lib", "lib"), ("util", "util")]);
+
+        // the member containing the start directory
+        let mut config = Config::for_tests(root);
+        assert_eq!(root_names(&config, &metadata).unwrap(), vec!["app"]);
+        config.start_dir = root.join("lib").join("src");
+        assert_eq!(root_names(&config, &metadata).unwrap(), vec!["lib"]);
+
+        config.all_members = true;
+        assert_eq!(root_names(&config, &metadata).unwrap(), vec!["app", "lib", "util"]);
+
+        let mut config = Config::for_tests(root);
+        config.packages = vec!["util".to_string(), "lib@0.1.0".to_string()];
+        assert_eq!(root_names(&config, &metadata).unwrap(), vec!["lib", "util"]);
+        config.packages = vec!["lib:0.2.0".to_string()];
+        assert!(root_names(&config, &metadata).is_err());
+
+        // excluding selects all other members
+        let mut config = Config::for_tests(root);
+        config.exclude_packages = vec!["app".to_string()];
+        assert_eq!(root_names(&config, &metadata).unwrap(), vec!["lib", "util"]);
+        config.exclude_packages = vec!["other".to_string()];
+        assert!(root_names(&config, &metadata).is_err());
+        config.exclude_packages = vec!["app".to_string(), "lib".to_string(), "util".to_string()];
+        assert!(root_names(&config, &metadata).is_err());
+    }
+
+    #[test]
+    fn root_tags_file_at_output() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let metadata = workspace(root, &[("app", "app")]);
+        let output = root.join("out").join("app.tags");
+
+        let mut config = Config::for_tests(root);
+        config.output = Some(TagsOutput::File(output.clone()));
+        assert_eq!(tags_files(&dependency_tree(&config, &metadata).unwrap()), vec![("app".to_string(), output)]);
+
+        // with workspace tags the output is used for them
+        config.workspace_tags = true;
+        assert_eq!(tags_files(&dependency_tree(&config, &metadata).unwrap()),
+                   vec![("app".to_string(), root.join("app").join("rusty-tags.vi"))]);
+
+        // the tags for stdout are written into a temporary file
+        config.workspace_tags = false;
+        config.output = Some(TagsOutput::Stdout);
+        let tags_files = tags_files(&dependency_tree(&config, &metadata).unwrap());
+        assert!(! tags_files[0].1.starts_with(root));
+        assert!(tags_files[0].1.to_string_lossy().ends_with(".vi.out"));
+    }
+
+    #[test]
+    fn member_tags_file_at_workspace_root() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let metadata = workspace(root, &[("app", ""), ("lib", "lib")]);
+
+        let mut config = Config::for_tests(root);
+        assert_eq!(tags_files(&dependency_tree(&config, &metadata).unwrap()),
+                   vec![("app".to_string(), root.join("rusty-tags.vi")), ("lib".to_string(), root.join("lib/rusty-tags.vi"))]);
+
+        // the workspace tags are written at the workspace root
+        config.workspace_tags = true;
+        assert_eq!(tags_files(&dependency_tree(&config, &metadata).unwrap()),
+                   vec![("app".to_string(), root.join("rusty-tags.member.vi")), ("lib".to_string(), root.join("lib/rusty-tags.vi"))]);
+    }
+
+    #[test]
+    fn single_member_tags_file_at_workspace_root() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let metadata = workspace(root, &[("app", "")]);
+
+        let mut config = Config::for_tests(root);
+        config.workspace_tags = true;
+        assert_eq!(tags_files(&dependency_tree(&config, &metadata).unwrap()),
+                   vec![("app".to_string(), root.join("rusty-tags.vi"))]);
+    }
+
+    #[test]
+    fn existing_out_dirs() {
+        let dir = TempDir::new().unwrap();
+        let build_dir = dir.path();
+        let build_script_dir = |name: &str, executed: bool| {
+            let script_dir = build_dir.join(name);
+            fs::create_dir_all(&script_dir).unwrap();
+            if executed {
+                fs::create_dir(script_dir.join("out")).unwrap();
+                File::create(script_dir.join("output")).unwrap();
+            }
+        };
+
+        build_script_dir("foo-0123456789abcdef", false);
+        build_script_dir("foo-fedcba9876543210", true);
+        build_script_dir("foo-bar-0a1b2c3d4e5f6a7b", true);
+        build_script_dir("baz-0123456789abcdef", true);
+        build_script_dir("baz-fedcba9876543210", true);
+
+        assert_eq!(existing_out_dir(build_dir, "foo"), Some(build_dir.join("foo-fedcba9876543210/out")));
+        assert_eq!(existing_out_dir(build_dir, "foo-bar"), Some(build_dir.join("foo-bar-0a1b2c3d4e5f6a7b/out")));
+
+        // the ones of multiple versions can't be told apart
+        assert_eq!(existing_out_dir(build_dir, "baz"), None);
+        assert_eq!(existing_out_dir(build_dir, "qux"), None);
+        assert_eq!(existing_out_dir(&build_dir.join("missing"), "foo"), None);
+    }
+}
@@ -1,5 +1,5 @@
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use rt_result::RtResult;
 
@@ -8,6 +8,7 @@ lazy_static! {
     static ref RUSTY_TAGS_DIR: RtResult<PathBuf> = rusty_tags_dir_internal();
     static ref RUSTY_TAGS_CACHE_DIR: RtResult<PathBuf> = rusty_tags_cache_dir_internal();
     static ref RUSTY_TAGS_LOCKS_DIR: RtResult<PathBuf> = rusty_tags_locks_dir_internal();
+    static ref RUSTY_TAGS_MIRROR_DIR: RtResult<PathBuf> = rusty_tags_mirror_dir_internal();
 }
 
 /// where rusty-tags puts all of its stuff
@@ -34,6 +35,37 @@ pub fn rusty_tags_locks_dir() -> RtResult<&'static Path> {
         .map_err(|err| err.clone())
 }
 
+/// where `rusty-tags` puts the tags files of sources, that
+/// shouldn't or can't be written into the source directory
+pub fn rusty_tags_mirror_dir() -> RtResult<&'static Path> {
+    RUSTY_TAGS_MIRROR_DIR
+        .as_ref()
+        .map(|pb| pb.as_path())
+        .map_err(|err| err.clone())
+}
+
+/// the directory inside of the mirror directory corresponding to `dir`,
+/// e.g. '/home/user/.cargo/registry/src/foo' is mirrored to
+/// '~/.rusty-tags/mirror/home/user/.cargo/registry/src/foo'
+pub fn mirrored_dir(dir: &Path) -> RtResult<PathBuf> {
+    let mut mirrored = rusty_tags_mirror_dir()?.to_path_buf();
+    for component in dir.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                // e.g. 'C:' on windows is mirrored to 'C'
+                let prefix = prefix.as_os_str().to_string_lossy().replace(|c: char| ! c.is_alphanumeric(), "");
+                mirrored.push(prefix);
+            }
+
+            Component::Normal(name) => mirrored.push(name),
+            Component::ParentDir    => { mirrored.pop(); }
+            Component::RootDir | Component::CurDir => {}
+        }
+    }
+
+    Ok(mirrored)
+}
+
 fn home_dir() -> RtResult<PathBuf> {
     HOME_DIR.clone()
 }
@@ -64,6 +96,15 @@ fn rusty_tags_locks_dir_internal() -> RtResult<PathBuf> {
     Ok(dir)
 }
 
+fn rusty_tags_mirror_dir_internal() -> RtResult<PathBuf> {
+    let dir = rusty_tags_dir()?.join("mirror");
+    if ! dir.is_dir() {
+        fs::create_dir_all(&dir)?;
+    }
+
+    Ok(dir)
+}
+
 fn rusty_tags_dir_internal() -> RtResult<PathBuf> {
     let dir = home_dir()?.join(".rusty-tags");
     if ! dir.is_dir() {
@@ -72,3 +113,19 @@ fn rusty_tags_dir_internal() -> RtResult<PathBuf> {
 
     Ok(dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    #[test]
+    fn mirrored_dirs() {
+        let mirror_dir = rusty_tags_mirror_dir().unwrap();
+        assert_eq!(mirrored_dir(Path::new("/home/user/.cargo/registry/src/foo")).unwrap(),
+                   mirror_dir.join("home/user/.cargo/registry/src/foo"));
+
+        assert_eq!(mirrored_dir(Path::new("/path/./to/../dep/")).unwrap(), mirror_dir.join("path/dep"));
+        assert_eq!(mirrored_dir(Path::new("/")).unwrap(), mirror_dir);
+    }
+}
@@ -0,0 +1,539 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use rt_result::RtResult;
+
+/// A section of an emacs tags file, which is either
+/// the tags of one source file or an include of an
+/// other tags file.
+enum Section<'a> {
+    /// the tags of the source file 'file_name'
+    Tags {
+        file_name: &'a [u8],
+        tags: &'a [u8]
+    },
+
+    /// an include of the tags file 'path'
+    Include {
+        path: PathBuf
+    }
+}
+
+/// Writes the tags of `lib_tags_file` and of `dependency_tags_files` into
+/// `into_tags_file`. Instead of writing include directives for the
+/// dependencies, their sections - and recursively the sections of their
+/// included tags files - are inlined, so that `into_tags_file` is
+/// self-contained.
+pub fn write_inlined(lib_tags_file: &Path,
+                     dependency_tags_files: &[&Path],
+                     into_tags_file: &Path)
+                     -> RtResult<()> {
+    let mut tags_files = vec![lib_tags_file];
+    tags_files.extend(dependency_tags_files);
+    write_merged(&tags_files, into_tags_file, true)
+}
+
+/// Writes the sections of all `tags_files` into `into_tags_file`, duplicated
+/// sections and include directives are only written once. If `inline_includes`
+/// is true, then the sections of included tags files are inlined.
+pub fn write_merged(tags_files: &[&Path], into_tags_file: &Path, inline_includes: bool) -> RtResult<()> {
+    let mut merger = Merger { inline_includes, ..Merger::default() };
+    for file in tags_files {
+        merger.merge(file)?;
+    }
+
+    let mut file = File::create(into_tags_file)?;
+    file.write_all(&merger.contents)?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Merger {
+    /// if the sections of included tags files are inlined
+    inline_includes: bool,
+
+    /// the already merged tags files, to prevent
+    /// merging a tags file multiple times
+    merged_files: FnvHashSet<PathBuf>,
+
+    /// the names of the source files of the already
+    /// written sections, to prevent duplicated sections
+    written_sections: FnvHashSet<Vec<u8>>,
+
+    /// the already written include directives
+    written_includes: FnvHashSet<PathBuf>,
+
+    /// the contents of the merged tags file
+    contents: Vec<u8>
+}
+
+impl Merger {
+    fn merge(&mut self, tags_file: &Path) -> RtResult<()> {
+        let tags_file = tags_file.canonicalize().unwrap_or_else(|_| tags_file.to_path_buf());
+        if ! self.merged_files.insert(tags_file.clone()) {
+            return Ok(());
+        }
+
+        let contents = {
+            let mut file = File::open(&tags_file)
+                .map_err(|err| format!("Couldn't open included tags file '{}': {}", tags_file.display(), err))?;
+
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)?;
+            contents
+        };
+
+        for section in parse_sections(&contents) {
+            match section {
+                Section::Tags { file_name, tags } => {
+                    if self.written_sections.insert(file_name.to_vec()) {
+                        write_section(&mut self.contents, file_name, tags)?;
+                    }
+                }
+
+                Section::Include { path } => {
+                    if self.inline_includes {
+                        if path.is_file() {
+                            self.merge(&path)?;
+                        }
+                    } else if self.written_includes.insert(path.clone()) {
+                        self.contents.extend_from_slice(b"\x0c\n");
+                        self.contents.extend_from_slice(path.to_string_lossy().as_bytes());
+                        self.contents.extend_from_slice(b",include\n");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the paths of the tags files directly included by `tags_file`.
+pub fn included_files(tags_file: &Path) -> RtResult<Vec<PathBuf>> {
+    let mut contents = Vec::new();
+    File::open(tags_file)?.read_to_end(&mut contents)?;
+
+    Ok(parse_sections(&contents).into_iter()
+        .filter_map(|section| match section {
+            Section::Include { path } => Some(path),
+            Section::Tags { .. }      => None
+        })
+        .collect())
+}
+
+/// Returns the paths of the source files with tags in `tags_file`.
+pub fn source_files(tags_file: &Path) -> RtResult<Vec<PathBuf>> {
+    let mut contents = Vec::new();
+    File::open(tags_file)?.read_to_end(&mut contents)?;
+
+    Ok(parse_sections(&contents).into_iter()
+        .filter_map(|section| match section {
+            Section::Tags { file_name, .. } => Some(PathBuf::from(String::from_utf8_lossy(file_name).into_owned())),
+            Section::Include { .. }         => None
+        })
+        .collect())
+}
+
+/// Removes the tags from `tags_file` for which `keep` - called with the
+/// source file and the line of the tag - returns false.
+pub fn filter<F>(tags_file: &Path, mut keep: F) -> RtResult<()>
+    where F: FnMut(&Path, usize) -> bool
+{
+    let mut contents = Vec::new();
+    File::open(tags_file)?.read_to_end(&mut contents)?;
+
+    let mut filtered = Vec::with_capacity(contents.len());
+    for section in parse_sections(&contents) {
+        match section {
+            Section::Tags { file_name, tags } => {
+                let source_file = PathBuf::from(String::from_utf8_lossy(file_name).into_owned());
+                let mut kept_tags = Vec::with_capacity(tags.len());
+                for tag in tags.split(|b| *b == b'\n').filter(|t| ! t.is_empty()) {
+                    // a tag is '{text}\x7f{name}\x01{line},{offset}' or '{text}\x7f{line},{offset}'
+                    let line = tag.iter()
+                        .rposition(|b| *b == b'\x01' || *b == b'\x7f')
+                        .and_then(|p| tag[p + 1..].split(|b| *b == b',').next())
+                        .and_then(|l| String::from_utf8_lossy(l).parse().ok());
+
+                    if line.map(|l| keep(&source_file, l)).unwrap_or(true) {
+                        kept_tags.extend_from_slice(tag);
+                        kept_tags.push(b'\n');
+                    }
+                }
+
+                // the sections of completely excluded source files are removed
+                if ! kept_tags.is_empty() || tags.is_empty() {
+                    write_section(&mut filtered, file_name, &kept_tags)?;
+                }
+            }
+
+            Section::Include { path } => {
+                filtered.extend_from_slice(b"\x0c\n");
+                filtered.extend_from_slice(path.to_string_lossy().as_bytes());
+                filtered.extend_from_slice(b",include\n");
+            }
+        }
+    }
+
+    File::create(tags_file)?.write_all(&filtered)?;
+    Ok(())
+}
+
+/// Converts the vi tags file `vi_tags_file` into the emacs tags file
+/// `emacs_tags_file`. The vi tags have to contain the line numbers of
+/// the tags - the 'line:' field - and the source files referenced by
+/// the tags have to be readable, because emacs tags contain the byte
+/// offsets of the tags.
+pub fn convert_from_vi(vi_tags_file: &Path, emacs_tags_file: &Path) -> RtResult<()> {
+    let vi_tags = {
+        let mut file = File::open(vi_tags_file)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        contents
+    };
+
+    // the vi tags are sorted by the tag name, but the emacs tags
+    // are grouped by their source file
+    let mut source_files: Vec<&str> = Vec::new();
+    let mut tags_by_file: FnvHashMap<&str, Vec<ViTag>> = FnvHashMap::default();
+    for line in vi_tags.lines() {
+        if line.starts_with('!') {
+            continue;
+        }
+
+        if let Some(tag) = ViTag::parse(line) {
+            tags_by_file.entry(tag.file)
+                .or_insert_with(|| {
+                    source_files.push(tag.file);
+                    Vec::new()
+                })
+                .push(tag);
+        }
+    }
+
+    let mut contents = Vec::new();
+    for source_file in &source_files {
+        let mut tags = tags_by_file.remove(source_file).unwrap_or_default();
+        tags.sort_by_key(|t| t.line);
+
+        let source = {
+            let mut source = Vec::new();
+            match File::open(source_file) {
+                Ok(mut file) => { file.read_to_end(&mut source)?; }
+                Err(_)       => continue
+            }
+
+            source
+        };
+
+        let mut line_starts = vec![0];
+        line_starts.extend(source.iter()
+            .enumerate()
+            .filter(|&(_, b)| *b == b'\n')
+            .map(|(i, _)| i + 1));
+
+        let mut section = Vec::new();
+        for tag in &tags {
+            let line_start = match line_starts.get(tag.line - 1) {
+                Some(start) => *start,
+                None        => continue
+            };
+
+            let line_end = source[line_start..].iter()
+                .position(|b| *b == b'\n' || *b == b'\r')
+                .map(|p| p + line_start)
+                .unwrap_or(source.len());
+
+            // the emacs tag text is the line up to the end of the tag name
+            let line = &source[line_start..line_end];
+            let text_end = line.windows(tag.name.len())
+                .position(|w| w == tag.name.as_bytes())
+                .map(|p| p + tag.name.len())
+                .unwrap_or(line.len());
+
+            section.extend_from_slice(&line[..text_end]);
+            section.push(b'\x7f');
+            section.extend_from_slice(tag.name.as_bytes());
+            section.push(b'\x01');
+            section.extend_from_slice(format!("{},{}\n", tag.line, line_start).as_bytes());
+        }
+
+        write_section(&mut contents, source_file.as_bytes(), &section)?;
+    }
+
+    let mut file = File::create(emacs_tags_file)?;
+    file.write_all(&contents)?;
+    Ok(())
+}
+
+/// The parts of a vi tag needed for the conversion to an emacs tag.
+struct ViTag<'a> {
+    name: &'a str,
+    file: &'a str,
+    line: usize
+}
+
+impl<'a> ViTag<'a> {
+    /// Parses a line of a vi tags file of the form
+    /// '{name}\t{file}\t{address};"\t{fields}', the 'line:'
+    /// field has to be present.
+    fn parse(line: &'a str) -> Option<ViTag<'a>> {
+        let mut parts = line.splitn(3, '\t');
+        let name = parts.next()?;
+        let file = parts.next()?;
+        let rest = parts.next()?;
+
+        // the address might contain tabs, so search the fields from the end
+        let fields = &rest[rest.rfind(";\"\t")? + 3..];
+        let line = fields.split('\t')
+            .filter_map(|f| f.strip_prefix("line:"))
+            .filter_map(|l| l.parse().ok())
+            .next()?;
+
+        if name.is_empty() || line == 0 {
+            return None;
+        }
+
+        Some(ViTag { name, file, line })
+    }
+}
+
+/// Writes the section of the source file `file_name` with the
+/// tags `tags`, the byte count of the section is computed from `tags`.
+pub fn write_section<W: Write>(writer: &mut W, file_name: &[u8], tags: &[u8]) -> RtResult<()> {
+    writer.write_all(b"\x0c\n")?;
+    writer.write_all(file_name)?;
+    writer.write_fmt(format_args!(",{}\n", tags.len()))?;
+    writer.write_all(tags)?;
+    Ok(())
+}
+
+/// Splits the contents of an emacs tags file into its sections.
+fn parse_sections(contents: &[u8]) -> Vec<Section<'_>> {
+    let mut sections = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = find(contents, pos, b"\x0c\n") {
+        let header_start = start + 2;
+        let header_end = match find(contents, header_start, b"\n") {
+            Some(end) => end,
+            None      => break
+        };
+
+        let header = &contents[header_start..header_end];
+        let body_start = header_end + 1;
+        let comma = match header.iter().rposition(|b| *b == b',') {
+            Some(comma) => comma,
+            None => {
+                pos = body_start;
+                continue;
+            }
+        };
+
+        let file_name = &header[..comma];
+        let size_or_include = &header[comma + 1..];
+        if size_or_include == b"include" {
+            let path = PathBuf::from(String::from_utf8_lossy(file_name).into_owned());
+            sections.push(Section::Include { path });
+            pos = body_start;
+            continue;
+        }
+
+        // the byte count of the section isn't trusted, because
+        // it might have been invalidated by a previous concatenation
+        // of tags files, the next section start is authoritative
+        let body_end = find(contents, body_start, b"\x0c\n").unwrap_or(contents.len());
+
+        // 'merge_tags' appends include directives without a preceding
+        // form feed, so they end up at the end of the previous section
+        let mut tags_end = body_end;
+        let mut includes = Vec::new();
+        while let Some(line_start) = last_line_start(contents, body_start, tags_end) {
+            let line = &contents[line_start..tags_end];
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            if line.contains(&b'\x7f') || ! line.ends_with(b",include") {
+                break;
+            }
+
+            let path = &line[..line.len() - b",include".len()];
+            includes.push(PathBuf::from(String::from_utf8_lossy(path).into_owned()));
+            tags_end = line_start;
+        }
+
+        sections.push(Section::Tags { file_name, tags: &contents[body_start..tags_end] });
+        for path in includes.into_iter().rev() {
+            sections.push(Section::Include { path });
+        }
+
+        pos = body_end;
+    }
+
+    sections
+}
+
+/// Returns the start of the last line in `contents[from..to]`.
+fn last_line_start(contents: &[u8], from: usize, to: usize) -> Option<usize> {
+    if to <= from {
+        return None;
+    }
+
+    let line = &contents[from..to - 1];
+    Some(line.iter().rposition(|b| *b == b'\n').map(|p| from + p + 1).unwrap_or(from))
+}
+
+fn find(haystack: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    if from >= haystack.len() {
+        return None;
+    }
+
+    haystack[from..]
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+    use super::*;
+
+    /// the sections as the file name with the tags, or the included path without tags
+    fn sections(contents: &[u8]) -> Vec<(String, Option<&[u8]>)> {
+        parse_sections(contents).into_iter()
+            .map(|section| match section {
+                Section::Tags { file_name, tags } => (String::from_utf8_lossy(file_name).into_owned(), Some(tags)),
+                Section::Include { path }         => (path.display().to_string(), None)
+            })
+            .collect()
+    }
+
+    fn write_include(contents: &mut Vec<u8>, path: &str) {
+        contents.extend_from_slice(b"\x0c\n");
+        contents.extend_from_slice(path.as_bytes());
+        contents.extend_from_slice(b",include\n");
+    }
+
+    fn read(file: &Path) -> Vec<u8> {
+        let mut contents = Vec::new();
+        File::open(file).unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    const LIB_TAGS: &[u8] = b"pub fn foo\x7ffoo\x011,0\nimpl Foo\x7f3,20\n";
+    const MAIN_TAGS: &[u8] = b"\tlet s = \"\x7f\"; let bar\x7fbar\x012,12\n";
+
+    #[test]
+    fn parse_sections_round_trip() {
+        let mut contents = Vec::new();
+        write_section(&mut contents, b"src/lib.rs", LIB_TAGS).unwrap();
+        write_include(&mut contents, "/deps/tags");
+        write_section(&mut contents, b"src/main.rs", MAIN_TAGS).unwrap();
+        write_section(&mut contents, b"src/empty.rs", b"").unwrap();
+
+        assert_eq!(sections(&contents), vec![("src/lib.rs".to_string(), Some(LIB_TAGS)),
+                                             ("/deps/tags".to_string(), None),
+                                             ("src/main.rs".to_string(), Some(MAIN_TAGS)),
+                                             ("src/empty.rs".to_string(), Some(&b""[..]))]);
+
+        let mut written = Vec::new();
+        for section in parse_sections(&contents) {
+            match section {
+                Section::Tags { file_name, tags } => write_section(&mut written, file_name, tags).unwrap(),
+                Section::Include { path }         => write_include(&mut written, &path.to_string_lossy())
+            }
+        }
+
+        assert_eq!(written, contents);
+    }
+
+    #[test]
+    fn parse_sections_with_appended_includes() {
+        // include directives appended without a form feed and an outdated byte count
+        let mut contents = b"\x0c\nsrc/lib.rs,3\n".to_vec();
+        contents.extend_from_slice(LIB_TAGS);
+        contents.extend_from_slice(b"/deps/a/tags,include\n/deps/b/tags,include\n");
+        write_section(&mut contents, b"src/main.rs", MAIN_TAGS).unwrap();
+
+        assert_eq!(sections(&contents), vec![("src/lib.rs".to_string(), Some(LIB_TAGS)),
+                                             ("/deps/a/tags".to_string(), None),
+                                             ("/deps/b/tags".to_string(), None),
+                                             ("src/main.rs".to_string(), Some(MAIN_TAGS))]);
+    }
+
+    #[test]
+    fn parse_sections_with_invalid_headers() {
+        assert!(sections(b"").is_empty());
+        assert!(sections(b"\x0c\nsrc/lib.rs").is_empty());
+        assert_eq!(sections(b"\x0c\nno-comma\n\x0c\nsrc/lib.rs,0\n"), vec![("src/lib.rs".to_string(), Some(&b""[..]))]);
+    }
+
+    #[test]
+    fn parse_vi_tags() {
+        let tag = ViTag::parse("foo\tsrc/lib.rs\t/^pub fn foo() {$/;\"\tf\tline:3").unwrap();
+        assert_eq!((tag.name, tag.file, tag.line), ("foo", "src/lib.rs", 3));
+
+        // the pattern contains tabs and a '\x7f'
+        let tag = ViTag::parse("bar\tsrc/main.rs\t/^\tlet s = \"\x7f\"; let bar\t= 1;$/;\"\tv\tline:12\tfunction:main").unwrap();
+        assert_eq!((tag.name, tag.file, tag.line), ("bar", "src/main.rs", 12));
+
+        assert!(ViTag::parse("foo\tsrc/lib.rs\t/^pub fn foo() {$/;\"\tf").is_none());
+        assert!(ViTag::parse("foo\tsrc/lib.rs\t/^pub fn foo() {$/;\"\tf\tline:0").is_none());
+        assert!(ViTag::parse("\tsrc/lib.rs\t/^pub fn foo() {$/;\"\tf\tline:3").is_none());
+        assert!(ViTag::parse("foo\tsrc/lib.rs").is_none());
+    }
+
+    #[test]
+    fn convert_vi_tags() {
+        let dir = TempDir::new().unwrap();
+        let lib_file = dir.path().join("lib.rs");
+        File::create(&lib_file).unwrap()
+            .write_all(b"pub struct Foo;\n\nfn main() {\n\tlet s = \"\x7f\"; let bar\t= 1;\n}\n").unwrap();
+
+        let lib = lib_file.to_str().unwrap();
+        let vi_tags_file = dir.path().join("tags");
+        File::create(&vi_tags_file).unwrap().write_all(format!(
+            "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/\n\
+             Foo\t{lib}\t/^pub struct Foo;$/;\"\ts\tline:1\n\
+             bar\t{lib}\t/^\tlet s = \"\x7f\"; let bar\t= 1;$/;\"\tv\tline:4\n\
+             main\t{lib}\t/^fn main() {{$/;\"\tf\tline:3\n\
+             missing\t{missing}\t/^fn missing() {{$/;\"\tf\tline:1\n",
+            lib = lib, missing = dir.path().join("missing.rs").display()).as_bytes()).unwrap();
+
+        let emacs_tags_file = dir.path().join("TAGS");
+        convert_from_vi(&vi_tags_file, &emacs_tags_file).unwrap();
+
+        let contents = read(&emacs_tags_file);
+        let tags: &[u8] = b"pub struct Foo\x7fFoo\x011,0\nfn main\x7fmain\x013,17\n\tlet s = \"\x7f\"; let bar\x7fbar\x014,29\n";
+        assert_eq!(sections(&contents), vec![(lib.to_string(), Some(tags))]);
+
+        // filtering without removing a tag keeps the tags file unchanged
+        filter(&emacs_tags_file, |_, _| true).unwrap();
+        assert_eq!(read(&emacs_tags_file), contents);
+    }
+
+    #[test]
+    fn filter_tags() {
+        let dir = TempDir::new().unwrap();
+        let tags_file = dir.path().join("TAGS");
+        let mut contents = Vec::new();
+        write_section(&mut contents, b"src/lib.rs", LIB_TAGS).unwrap();
+        write_include(&mut contents, "/deps/tags");
+        write_section(&mut contents, b"src/main.rs", MAIN_TAGS).unwrap();
+        fs::write(&tags_file, &contents).unwrap();
+
+        let mut lines = Vec::new();
+        filter(&tags_file, |file, line| { lines.push((file.to_path_buf(), line)); line != 3 && line != 2 }).unwrap();
+        assert_eq!(lines, vec![(PathBuf::from("src/lib.rs"), 1), (PathBuf::from("src/lib.rs"), 3),
+                               (PathBuf::from("src/main.rs"), 2)]);
+
+        // the tag without a name is removed and the section of 'src/main.rs' is empty
+        assert_eq!(sections(&read(&tags_file)), vec![("src/lib.rs".to_string(), Some(&b"pub fn foo\x7ffoo\x011,0\n"[..])),
+                                                     ("/deps/tags".to_string(), None)]);
+    }
+}
@@ -0,0 +1,332 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fnv::FnvHashSet;
+
+use rt_result::RtResult;
+use glob;
+
+/// The files and directories excluded from the tags of a source.
+#[derive(Default, Debug)]
+pub struct Excludes {
+    /// the exclude patterns, see 'Rule'
+    pub patterns: Vec<String>,
+
+    /// the directory the patterns containing a '/' are relative to,
+    /// the package directory of the source
+    pub base_dir: Option<PathBuf>,
+
+    /// respect '.gitignore' and '.ignore' files
+    pub gitignore: bool
+}
+
+impl Excludes {
+    /// if nothing is excluded, so that the directories can be given directly to ctags
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && ! self.gitignore
+    }
+}
+
+/// Collects the files inside of `dirs` recursively, without the ones excluded by
+/// `excludes`. Hidden files and directories - e.g. '.git' - are always excluded.
+pub fn collect_files<P: AsRef<Path>>(dirs: &[P], excludes: &Excludes) -> RtResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir in dirs {
+        let dir = dir.as_ref();
+        let base_dir = match excludes.base_dir {
+            Some(ref base_dir) if dir.starts_with(base_dir) => base_dir.as_path(),
+            _                                               => dir
+        };
+
+        // the configured patterns can't be overridden by the ignore files
+        let exclude_rules = [RuleSet::new(base_dir, excludes.patterns.iter().map(String::as_str))];
+        if dir != base_dir && is_excluded(&exclude_rules, dir, true) {
+            continue;
+        }
+
+        let mut ignore_rules = Vec::new();
+        if excludes.gitignore {
+            ignore_rules.extend(parent_ignore_files(dir));
+            ignore_rules.extend(RuleSet::from_ignore_files(dir)?);
+        }
+
+        let mut visited_dirs = FnvHashSet::default();
+        visited_dirs.extend(fs::canonicalize(dir).ok());
+        collect_dir_files(dir, &exclude_rules, &mut ignore_rules, excludes.gitignore, &mut visited_dirs, &mut files)?;
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+fn collect_dir_files(dir: &Path,
+                     exclude_rules: &[RuleSet],
+                     ignore_rules: &mut Vec<RuleSet>,
+                     gitignore: bool,
+                     visited_dirs: &mut FnvHashSet<PathBuf>,
+                     files: &mut Vec<PathBuf>)
+                     -> RtResult<()> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        entries.push(entry?.path());
+    }
+
+    entries.sort();
+    for path in entries {
+        let is_hidden = path.file_name().map(|n| n.to_string_lossy().starts_with('.')).unwrap_or(true);
+        if is_hidden {
+            continue;
+        }
+
+        // follows symlinks like 'ctags --recurse'
+        let is_dir = match fs::metadata(&path) {
+            Ok(metadata) => metadata.is_dir(),
+            Err(_)       => continue
+        };
+
+        if is_excluded(exclude_rules, &path, is_dir) || is_excluded(ignore_rules, &path, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            // a symlink might point to an already visited directory, e.g. to a parent
+            let is_new_dir = fs::canonicalize(&path).map(|p| visited_dirs.insert(p)).unwrap_or(false);
+            if ! is_new_dir {
+                continue;
+            }
+
+            let num_ignore_rules = ignore_rules.len();
+            if gitignore {
+                ignore_rules.extend(RuleSet::from_ignore_files(&path)?);
+            }
+
+            collect_dir_files(&path, exclude_rules, ignore_rules, gitignore, visited_dirs, files)?;
+            ignore_rules.truncate(num_ignore_rules);
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// If `path` is excluded by `rule_sets`, the last matching rule wins.
+fn is_excluded(rule_sets: &[RuleSet], path: &Path, is_dir: bool) -> bool {
+    let mut excluded = false;
+    for rule_set in rule_sets {
+        let rel_path = match path.strip_prefix(&rule_set.base_dir) {
+            Ok(rel_path) => rel_path.to_string_lossy().replace('\\', "/"),
+            Err(_)       => continue
+        };
+
+        for rule in &rule_set.rules {
+            if rule.matches(&rel_path, is_dir) {
+                excluded = ! rule.negated;
+            }
+        }
+    }
+
+    excluded
+}
+
+/// The rules of the '.gitignore' and '.ignore' files in the parent directories
+/// of `dir`, up to the root of the git repository containing `dir`. Outside of
+/// a git repository there're no rules.
+fn parent_ignore_files(dir: &Path) -> Vec<RuleSet> {
+    let mut rule_sets = Vec::new();
+    let mut current = dir;
+    while ! current.join(".git").exists() {
+        current = match current.parent() {
+            Some(parent) => parent,
+            None         => return Vec::new()
+        };
+
+        if let Ok(parent_rule_sets) = RuleSet::from_ignore_files(current) {
+            // the rules of the outer directories have to come first
+            rule_sets.splice(0..0, parent_rule_sets);
+        }
+    }
+
+    rule_sets
+}
+
+/// The rules of one '.gitignore' or '.ignore' file, or of the configured
+/// exclude patterns, and the directory the rules are relative to.
+struct RuleSet {
+    base_dir: PathBuf,
+    rules: Vec<Rule>
+}
+
+impl RuleSet {
+    fn new<'a, I: Iterator<Item = &'a str>>(base_dir: &Path, patterns: I) -> RuleSet {
+        RuleSet {
+            base_dir: base_dir.to_path_buf(),
+            rules: patterns.filter_map(Rule::parse).collect()
+        }
+    }
+
+    fn from_ignore_files(dir: &Path) -> RtResult<Vec<RuleSet>> {
+        let mut rule_sets = Vec::new();
+        for file_name in &[".gitignore", ".ignore"] {
+            let file = dir.join(file_name);
+            if file.is_file() {
+                // the patterns might contain file names which aren't valid UTF-8
+                let contents = fs::read(&file)?;
+                rule_sets.push(RuleSet::new(dir, String::from_utf8_lossy(&contents).lines()));
+            }
+        }
+
+        Ok(rule_sets)
+    }
+}
+
+/// An exclude pattern with the syntax of '.gitignore' files: a pattern
+/// without a '/' matches the name of a file or directory at any depth,
+/// otherwise it matches the path relative to the base directory. A
+/// trailing '/' only matches directories and a leading '!' re-includes
+/// the paths excluded by a previous pattern.
+struct Rule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None          => (false, line)
+        };
+
+        let (dir_only, pattern) = match pattern.strip_suffix('/') {
+            Some(pattern) => (true, pattern),
+            None          => (false, pattern)
+        };
+
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Rule { pattern: pattern.to_string(), negated, dir_only, anchored })
+    }
+
+    fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && ! is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            glob::matches_path(&self.pattern, rel_path)
+        } else {
+            let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+            glob::matches_path(&self.pattern, name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use tempfile::TempDir;
+    use super::*;
+
+    fn rule(line: &str) -> Rule {
+        Rule::parse(line).expect("Expected a valid rule")
+    }
+
+    fn create_files(dir: &Path, files: &[&str]) {
+        for file in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+    }
+
+    fn collect(dir: &Path, patterns: &[&str], gitignore: bool) -> Vec<PathBuf> {
+        let excludes = Excludes {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            base_dir: Some(dir.to_path_buf()),
+            gitignore
+        };
+
+        collect_files(&[dir], &excludes).unwrap().iter()
+            .map(|f| f.strip_prefix(dir).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        assert!(Rule::parse("").is_none());
+        assert!(Rule::parse("  # comment").is_none());
+        assert!(Rule::parse("/").is_none());
+
+        let negated = rule("!foo.rs");
+        assert!(negated.negated && ! negated.dir_only && ! negated.anchored);
+
+        let dir_only = rule("target/");
+        assert!(dir_only.dir_only && ! dir_only.anchored);
+        assert_eq!(dir_only.pattern, "target");
+
+        let anchored = rule("/src/gen");
+        assert!(anchored.anchored);
+        assert_eq!(anchored.pattern, "src/gen");
+    }
+
+    #[test]
+    fn match_rules() {
+        assert!(rule("*.bak").matches("a/b/c.bak", false));
+        assert!(rule("target/").matches("target", true));
+        assert!(! rule("target/").matches("target", false));
+        assert!(rule("target/").matches("a/target", true));
+        assert!(rule("/gen").matches("gen", true));
+        assert!(! rule("/gen").matches("src/gen", true));
+        assert!(rule("src/gen").matches("src/gen", false));
+        assert!(! rule("src/gen").matches("a/src/gen", false));
+        assert!(rule("**/fixtures").matches("fixtures", true));
+        assert!(rule("**/fixtures").matches("tests/data/fixtures", true));
+        assert!(rule("tests/**/*.rs").matches("tests/a/b.rs", false));
+    }
+
+    #[test]
+    fn excluded_by_last_matching_rule() {
+        let rule_sets = [RuleSet::new(Path::new("/p"), ["*.rs", "!keep.rs"].iter().cloned())];
+        assert!(is_excluded(&rule_sets, Path::new("/p/src/a.rs"), false));
+        assert!(! is_excluded(&rule_sets, Path::new("/p/src/keep.rs"), false));
+        assert!(! is_excluded(&rule_sets, Path::new("/other/a.rs"), false));
+    }
+
+    #[test]
+    fn collect_with_patterns() {
+        let dir = TempDir::new().unwrap();
+        create_files(dir.path(), &["src/lib.rs", "src/gen/a.rs", "tests/fixtures/b.rs", ".hidden/c.rs"]);
+        assert_eq!(collect(dir.path(), &["gen/", "tests/fixtures"], false), vec![PathBuf::from("src/lib.rs")]);
+    }
+
+    #[test]
+    fn collect_with_non_utf8_gitignore() {
+        let dir = TempDir::new().unwrap();
+        create_files(dir.path(), &["src/lib.rs", "src/gen.rs"]);
+        File::create(dir.path().join(".gitignore")).unwrap().write_all(b"gen.rs\n\xe9.rs\n").unwrap();
+        assert_eq!(collect(dir.path(), &[], true), vec![PathBuf::from("src/lib.rs")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_with_symlink_cycle() {
+        let dir = TempDir::new().unwrap();
+        create_files(dir.path(), &["src/lib.rs"]);
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("src/cycle")).unwrap();
+        assert_eq!(collect(dir.path(), &[], false), vec![PathBuf::from("src/lib.rs")]);
+    }
+}
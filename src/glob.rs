@@ -0,0 +1,88 @@
+/// If `text` matches the glob `pattern`, where '*' matches
+/// any number of characters and '?' matches one character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text, true)
+}
+
+/// If the relative `path` - with '/' as separator - matches the glob `pattern`,
+/// where '*' and '?' don't match a '/' and '**' matches any number of
+/// directories, e.g. 'tests/**/*.rs' matches 'tests/a.rs' and 'tests/a/b.rs'.
+pub fn matches_path(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches_chars(&pattern, &path, false)
+}
+
+/// If `pattern` matches `text`, by computing for all positions - backwards from the
+/// ends - if the rest of the pattern matches the rest of the text, which bounds the
+/// time by the product of their lengths.
+fn matches_chars(pattern: &[char], text: &[char], cross_dirs: bool) -> bool {
+    let width = text.len() + 1;
+
+    // 'matched[i * width + j]' is if 'pattern[i..]' matches 'text[j..]'
+    let mut matched = vec![false; (pattern.len() + 1) * width];
+    matched[pattern.len() * width + text.len()] = true;
+    let is_matched = |matched: &[bool], i: usize, j: usize| matched[i * width + j];
+
+    for i in (0..pattern.len()).rev() {
+        for j in (0..=text.len()).rev() {
+            let has_char = j < text.len();
+            let has_file_char = has_char && (cross_dirs || text[j] != '/');
+            let is_match = match pattern[i] {
+                '*' if pattern.get(i + 1) == Some(&'*') => {
+                    // '**/' also matches no directory at all
+                    (pattern.get(i + 2) == Some(&'/') && is_matched(&matched, i + 3, j))
+                        || is_matched(&matched, i + 2, j)
+                        || (has_char && is_matched(&matched, i, j + 1))
+                }
+
+                '*' => is_matched(&matched, i + 1, j) || (has_file_char && is_matched(&matched, i, j + 1)),
+                '?' => has_file_char && is_matched(&matched, i + 1, j + 1),
+                c   => has_char && text[j] == c && is_matched(&matched, i + 1, j + 1)
+            };
+
+            matched[i * width + j] = is_match;
+        }
+    }
+
+    matched[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names() {
+        assert!(matches("*-sys", "openssl-sys"));
+        assert!(matches("serde*", "serde"));
+        assert!(matches("s?rde", "serde"));
+        assert!(matches("*", ""));
+        assert!(! matches("*-sys", "openssl"));
+        assert!(! matches("s?rde", "srde"));
+        assert!(matches("a*", "a/b"));
+    }
+
+    #[test]
+    fn matches_paths() {
+        assert!(matches_path("tests/*.rs", "tests/a.rs"));
+        assert!(! matches_path("tests/*.rs", "tests/a/b.rs"));
+        assert!(! matches_path("a?b", "a/b"));
+        assert!(matches_path("tests/**/*.rs", "tests/a.rs"));
+        assert!(matches_path("tests/**/*.rs", "tests/a/b/c.rs"));
+        assert!(matches_path("**/fixtures", "fixtures"));
+        assert!(matches_path("**/fixtures", "a/b/fixtures"));
+        assert!(matches_path("a/**", "a/b/c"));
+        assert!(! matches_path("**/fixtures", "a/fixtures2"));
+    }
+
+    #[test]
+    fn matches_many_stars() {
+        let text = "a".repeat(200);
+        assert!(! matches(&("a*".repeat(30) + "b"), &text));
+        assert!(! matches_path(&("**/a*".repeat(30) + "b"), &text));
+        assert!(matches(&"a*".repeat(30), &text));
+    }
+}
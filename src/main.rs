@@ -10,6 +10,10 @@ extern crate serde_json;
 extern crate fnv;
 extern crate semver;
 extern crate dirs as extern_dirs;
+extern crate fs2;
+extern crate hostname;
+extern crate ctrlc;
+extern crate wait_timeout;
 
 #[macro_use]
 extern crate serde_derive;
@@ -20,18 +24,19 @@ extern crate clap;
 #[macro_use]
 extern crate lazy_static;
 
-use std::path::Path;
+use std::path::PathBuf;
+use std::fs::File;
 use std::io::{self, Write};
 use std::process::Command;
 use std::env;
 
-use tempfile::NamedTempFile;
 
 use rt_result::RtResult;
 use dependencies::dependency_tree;
-use tags::{update_tags, create_tags, move_tags};
+use tags::{update_tags, update_workspace_tags};
 use config::Config;
-use types::SourceLock;
+use types::{SourceLock, TagsOutput};
+use std_lib::update_std_lib_tags;
 
 #[macro_use]
 mod output;
@@ -40,6 +45,15 @@ mod rt_result;
 mod dependencies;
 mod dirs;
 mod tags;
+mod etags;
+mod cache;
+mod clean;
+mod cleanup;
+mod probe;
+mod glob;
+mod files;
+mod cfg;
+mod std_lib;
 mod types;
 mod config;
 
@@ -51,25 +65,49 @@ fn main() {
 }
 
 fn execute() -> RtResult<()> {
+    cleanup::install_signal_handler()?;
     let config = Config::from_command_args()?;
-    update_all_tags(&config)?;
+    match config.command {
+        config::Command::UpdateTags           => update_all_tags(&config)?,
+        config::Command::Cache(ref cache_cmd) => cache::execute(&config, cache_cmd)?,
+
+        config::Command::Clean { all: true, dry_run } => clean::clean_all(&config, dry_run)?,
+
+        config::Command::Clean { all: false, dry_run } => {
+            let metadata = fetch_source_and_metadata(&config)?;
+            let dep_tree = dependency_tree(&config, &metadata)?;
+            clean::clean_project(&config, &dep_tree, dry_run)?
+        }
+    }
+
     Ok(())
 }
 
 fn update_all_tags(config: &Config) -> RtResult<()> {
     let metadata = fetch_source_and_metadata(&config)?;
-    update_std_lib_tags(&config)?;
+    let std_lib_tags = update_std_lib_tags(config)?;
 
     let mut source_locks = Vec::new();
     let dep_tree = {
         let mut dep_tree = dependency_tree(&config, &metadata)?;
+        if config.output.is_some() && ! config.workspace_tags && dep_tree.roots().count() > 1 {
+            return Err("The option '--output' with a path or '-' needs the option '--workspace-tags' for cargo projects with multiple workspace members!".into());
+        }
+
         let unlocked_root_ids: Vec<_> = {
             let mut unlocked_roots = Vec::new();
             for source in dep_tree.roots() {
-                match source.lock(&config.tags_spec)? {
-                    SourceLock::AlreadyLocked { ref path } => {
-                        info!(config, "Already creating tags for '{}', if this isn't the case remove the lock file '{}'",
-                              source.name, path.display());
+                match source.lock(config)? {
+                    SourceLock::AlreadyLocked { ref path, ref holder } => {
+                        let holder_str = holder.as_ref().map(|h| format!(" by {}", h)).unwrap_or_default();
+                        let msg = format!("Already creating tags for '{}'{}, lock file '{}', use '--wait' to wait for it",
+                                          source.name, holder_str, path.display());
+
+                        if config.output == Some(TagsOutput::Stdout) {
+                            return Err(msg.into());
+                        }
+
+                        info!(config, "{}", msg);
                         continue;
                     }
 
@@ -91,7 +129,36 @@ fn update_all_tags(config: &Config) -> RtResult<()> {
         dep_tree
     };
 
-    update_tags(&config, &dep_tree)?;
+    update_tags(config, &dep_tree, std_lib_tags.as_ref())?;
+
+    let workspace_tags_files = if config.workspace_tags {
+        update_workspace_tags(config, &dep_tree)?
+    } else {
+        Vec::new()
+    };
+
+    if config.output == Some(TagsOutput::Stdout) {
+        if config.workspace_tags {
+            write_tags_to_stdout(workspace_tags_files.iter())?;
+        } else {
+            write_tags_to_stdout(dep_tree.roots().flat_map(|r| r.tags_files.iter().map(|f| &f.tags_file)))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_tags_to_stdout<'a, I>(tags_files: I) -> RtResult<()>
+    where I: Iterator<Item=&'a PathBuf>
+{
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for file in tags_files {
+        let mut tags_file = File::open(file)?;
+        io::copy(&mut tags_file, &mut stdout)?;
+    }
+
+    stdout.flush()?;
     Ok(())
 }
 
@@ -104,7 +171,7 @@ fn fetch_source_and_metadata(config: &Config) -> RtResult<serde_json::Value> {
     cmd.arg("metadata");
     cmd.arg("--format-version=1");
 
-    let output = cmd.output()
+    let output = cleanup::run(&mut cmd)
         .map_err(|err| format!("'cargo' execution failed: {}\nIs 'cargo' correctly installed?", err))?;
 
     if ! output.status.success() {
@@ -118,89 +185,3 @@ fn fetch_source_and_metadata(config: &Config) -> RtResult<serde_json::Value> {
 
     Ok(serde_json::from_str(&String::from_utf8_lossy(&output.stdout))?)
 }
-
-fn update_std_lib_tags(config: &Config) -> RtResult<()> {
-    let src_path_str = env::var("RUST_SRC_PATH");
-    if ! src_path_str.is_ok() {
-        return Ok(());
-    }
-
-    let src_path_str = src_path_str.unwrap();
-    let src_path = Path::new(&src_path_str);
-    if ! src_path.is_dir() {
-        return Err(format!("Missing rust source code at '{}'!", src_path.display()).into());
-    }
-
-    let output_path = match config.output_dir_std {
-        Some(ref path_buf) => path_buf.as_path(),
-        None => src_path,
-    };
-    let std_lib_tags = output_path.join(config.tags_spec.file_name());
-    if std_lib_tags.is_file() && ! config.force_recreate {
-        return Ok(());
-    }
-
-    let possible_src_dirs = [
-        // rustc >= 1.47.0
-        "alloc",
-        "core",
-        "panic_abort",
-        "panic_unwind",
-        "proc_macro",
-        "profiler_builtins",
-        "rtstartup",
-        "std",
-        "stdarch",
-        "term",
-        "test",
-        "unwind",
-
-        // rustc < 1.47.0
-        "liballoc",
-        "libarena",
-        "libbacktrace",
-        "libcollections",
-        "libcore",
-        "libflate",
-        "libfmt_macros",
-        "libgetopts",
-        "libgraphviz",
-        "liblog",
-        "librand",
-        "librbml",
-        "libserialize",
-        "libstd",
-        "libsyntax",
-        "libterm"
-    ];
-
-    let mut src_dirs = Vec::new();
-    for dir in &possible_src_dirs {
-        let src_dir = src_path.join(&dir);
-        if src_dir.is_dir() {
-            src_dirs.push(src_dir);
-        }
-    }
-
-    if src_dirs.is_empty() {
-        return Err(format!(r#"
-No source directories found for standard library source at $RUST_SRC_PATH:
-    '{}'
-
-Please set the standard library source path depending on your rustc version.
-
-For rustc >= 1.47.0:
-    $ export RUST_SRC_PATH=$(rustc --print sysroot)/lib/rustlib/src/rust/library/
-
-For rustc < 1.47.0:
-    $ export RUST_SRC_PATH=$(rustc --print sysroot)/lib/rustlib/src/rust/src/"#, src_path.display()).into());
-    }
-
-    info!(config, "Creating tags for the standard library ...");
-
-    let tmp_std_lib_tags = NamedTempFile::new_in(&output_path)?;
-    create_tags(config, &src_dirs, tmp_std_lib_tags.path())?;
-    move_tags(config, tmp_std_lib_tags.path(), &std_lib_tags)?;
-
-    Ok(())
-}
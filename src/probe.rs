@@ -0,0 +1,236 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, rename};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use tempfile::TempDir;
+
+use rt_result::RtResult;
+use types::TagsExe;
+use dirs::rusty_tags_dir;
+use cleanup::{self, TempFile};
+
+/// The capabilities of a ctags executable, which are probed
+/// by calling it with `--version`, `--help`, `--list-features`,
+/// `--list-languages`, `--list-maps` and with `--recurse` on an
+/// empty directory.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TagsCapabilities {
+    /// the first line of the version output
+    pub version: String,
+
+    /// supports JSON output with '--output-format=json'
+    pub json: bool,
+
+    /// supports emacs tags with '-e'
+    pub etags: bool,
+
+    /// supports the selection of the fields of vi tags with '--fields'
+    pub fields: bool,
+
+    /// supports the selection of extra tags with '--extras'
+    pub extras: bool,
+
+    /// has a built-in Rust parser, otherwise the
+    /// Rust tags are defined by regular expressions
+    pub rust_parser: bool,
+
+    /// the enabled languages with a built-in parser
+    pub languages: Vec<String>,
+
+    /// the file name patterns of the languages, e.g. '*.rs' for Rust
+    pub language_maps: Vec<(String, Vec<String>)>,
+
+    /// supports '--recurse'
+    pub recurse: bool
+}
+
+impl fmt::Display for TagsCapabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        write!(f, "version: '{}', json: {}, etags: {}, fields: {}, extras: {}, rust parser: {}, recurse: {}",
+               self.version, yes_no(self.json), yes_no(self.etags), yes_no(self.fields),
+               yes_no(self.extras), yes_no(self.rust_parser), yes_no(self.recurse))
+    }
+}
+
+/// Searches the ctags executable - `ctags_exe` or the default names - and probes its capabilities.
+pub fn detect_tags_exe(ctags_exe: &Option<String>) -> RtResult<(TagsExe, TagsCapabilities)> {
+    let exes = match *ctags_exe {
+        Some(ref exe) if ! exe.is_empty() => vec![exe.as_str()],
+        _                          => vec!["ctags", "exuberant-ctags", "exctags", "universal-ctags", "uctags"]
+    };
+
+    let mut without_version = Vec::new();
+    for exe in &exes {
+        let output = match cleanup::run(Command::new(exe).arg("--version")) {
+            Ok(output) => output,
+            Err(_)     => continue
+        };
+
+        // e.g. the ctags shipped with MacOS doesn't support '--version'
+        if ! output.status.success() {
+            without_version.push(*exe);
+            continue;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = stdout.lines().next().unwrap_or("").trim().to_string();
+        let tags_exe = if stdout.contains("Universal Ctags") {
+            TagsExe::UniversalCtags(exe.to_string())
+        } else {
+            TagsExe::ExuberantCtags(exe.to_string())
+        };
+
+        let caps = probe_cached(&tags_exe, version)?;
+        return Ok((tags_exe, caps));
+    }
+
+    if ! without_version.is_empty() {
+        return Err(format!("The 'ctags' executables {:?} don't support '--version', they're most likely too old and don't support '--recurse'. \
+                            Please install 'universal-ctags' or 'exuberant-ctags'!", without_version).into());
+    }
+
+    Err(format!("Couldn't find 'ctags' executable! Searched for executables with names: {:?}. Is 'ctags' correctly installed?", &exes).into())
+}
+
+/// The capabilities of a ctags executable probed by an earlier run, which
+/// are stored in '~/.rusty-tags/ctags.json'.
+#[derive(Serialize, Deserialize, Debug)]
+struct ProbedExe {
+    /// the path of the executable
+    path: PathBuf,
+
+    /// the modification time of the executable
+    modified: SystemTime,
+
+    capabilities: TagsCapabilities
+}
+
+/// Probes the capabilities of `exe`, or takes them from the ones of an earlier
+/// run, if the path, the modification time and the `version` of `exe` didn't change.
+fn probe_cached(exe: &TagsExe, version: String) -> RtResult<TagsCapabilities> {
+    let exe_name = match *exe {
+        TagsExe::ExuberantCtags(ref exe_name) | TagsExe::UniversalCtags(ref exe_name) => exe_name
+    };
+
+    let probed_file = rusty_tags_dir()?.join("ctags.json");
+    let path_and_modified = exe_path(exe_name)
+        .and_then(|path| fs::metadata(&path).and_then(|m| m.modified()).ok().map(|modified| (path, modified)));
+
+    let (path, modified) = match path_and_modified {
+        Some(path_and_modified) => path_and_modified,
+        None                    => return probe(exe, version)
+    };
+
+    let mut probed_exes: Vec<ProbedExe> = File::open(&probed_file).ok()
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default();
+
+    if let Some(probed) = probed_exes.iter().find(|p| p.path == path && p.modified == modified && p.capabilities.version == version) {
+        return Ok(probed.capabilities.clone());
+    }
+
+    let caps = probe(exe, version)?;
+    probed_exes.retain(|p| p.path != path);
+    probed_exes.push(ProbedExe { path, modified, capabilities: caps.clone() });
+
+    // the capabilities are probed again if they can't be stored
+    let _ = save_probed_exes(&probed_exes, &probed_file);
+    Ok(caps)
+}
+
+fn save_probed_exes(probed_exes: &[ProbedExe], probed_file: &Path) -> RtResult<()> {
+    let mut tmp_file = TempFile::new_in(rusty_tags_dir()?)?;
+    tmp_file.write_all(serde_json::to_string_pretty(probed_exes)?.as_bytes())?;
+    rename(tmp_file.path(), probed_file)?;
+    Ok(())
+}
+
+/// The path of the executable `exe_name`, which is searched in the directories
+/// of 'PATH' if it's only a file name.
+fn exe_path(exe_name: &str) -> Option<PathBuf> {
+    let path = Path::new(exe_name);
+    if path.components().count() > 1 {
+        return Some(path.to_path_buf());
+    }
+
+    let file_names = [exe_name.to_string(), format!("{}{}", exe_name, env::consts::EXE_SUFFIX)];
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(|dir| file_names.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+fn probe(exe: &TagsExe, version: String) -> RtResult<TagsCapabilities> {
+    let exe_name = match *exe {
+        TagsExe::ExuberantCtags(ref exe_name) | TagsExe::UniversalCtags(ref exe_name) => exe_name
+    };
+
+    let help = output_lines(exe_name, &["--help"]);
+    let has_option = |opt: &str| help.iter().any(|l| l.starts_with(opt) || l.contains(&format!(" {}", opt)));
+
+    let features = match *exe {
+        TagsExe::UniversalCtags(_) => output_lines(exe_name, &["--list-features"]),
+        TagsExe::ExuberantCtags(_) => Vec::new()
+    };
+
+    // the languages might be followed by e.g. '[disabled]'
+    let languages: Vec<String> = output_lines(exe_name, &["--list-languages"]).iter()
+        .filter(|l| ! l.contains("disabled"))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(String::from)
+        .collect();
+
+    // exuberant ctags lists the extensions - e.g. '.rs' - instead of patterns
+    let language_maps = output_lines(exe_name, &["--list-maps"]).iter()
+        .filter(|l| ! l.starts_with('#'))
+        .filter_map(|l| {
+            let mut words = l.split_whitespace();
+            let language = words.next()?.to_string();
+            let patterns = words
+                .map(|w| if w.starts_with('.') { format!("*{}", w) } else { w.to_string() })
+                .collect();
+
+            Some((language, patterns))
+        })
+        .collect();
+
+    Ok(TagsCapabilities {
+        version,
+        json: features.iter().any(|f| f.split_whitespace().next() == Some("json")),
+        etags: has_option("-e ") || help.iter().any(|l| l.contains("--output-format=") && l.contains("etags")),
+        fields: has_option("--fields"),
+        extras: has_option("--extras"),
+        rust_parser: languages.iter().any(|l| l == "Rust"),
+        languages,
+        language_maps,
+        recurse: recurse_works(exe_name)?
+    })
+}
+
+/// Calls `exe_name` with `args` and returns the trimmed lines of its
+/// output, an empty list if the call fails.
+fn output_lines(exe_name: &str, args: &[&str]) -> Vec<String> {
+    match cleanup::run(Command::new(exe_name).args(args)) {
+        Ok(ref output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|l| l.trim().to_string())
+                .filter(|l| ! l.is_empty())
+                .collect()
+        }
+
+        _ => Vec::new()
+    }
+}
+
+/// If `exe_name` accepts `--recurse` by running it on an empty directory.
+fn recurse_works(exe_name: &str) -> RtResult<bool> {
+    let dir = TempDir::new()?;
+    cleanup::register_temp_dir(dir.path());
+    let output = cleanup::run(Command::new(exe_name).arg("--recurse").arg("-f").arg("-").arg(dir.path()));
+    Ok(output.map(|o| o.status.success()).unwrap_or(false))
+}
@@ -39,6 +39,12 @@ impl From<serde_json::Error> for RtErr {
     }
 }
 
+impl From<ctrlc::Error> for RtErr {
+    fn from(err: ctrlc::Error) -> RtErr {
+        RtErr::Message(format!("{}", err))
+    }
+}
+
 impl From<String> for RtErr {
     fn from(s: String) -> RtErr {
         RtErr::Message(s)
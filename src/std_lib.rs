@@ -0,0 +1,601 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use rt_result::RtResult;
+use config::Config;
+use types::{Source, TagsKind};
+use dirs::rusty_tags_cache_dir;
+use tags::{create_all_tags, move_tags, copy_tags};
+use cache::{self, CacheInfo, TagsKey};
+use cleanup::{self, TempFile};
+use files::Excludes;
+use cfg::{self, CodeFilter};
+
+/// Where the source of the standard library was found.
+pub enum StdLibSrc {
+    /// the source directory containing the crates of the
+    /// standard library and the toolchain it belongs to
+    Found { src_path: PathBuf, toolchain: Toolchain },
+
+    /// the 'rust-src' component of the toolchain isn't installed
+    Missing { toolchain: Toolchain },
+
+    /// no toolchain was found, 'rustc' couldn't be executed
+    NoToolchain
+}
+
+/// A rust toolchain identified by the output of 'rustc -vV'.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Hash, Clone, Debug)]
+pub struct Toolchain {
+    /// the sysroot of the toolchain
+    pub sysroot: PathBuf,
+
+    /// the release, e.g. '1.47.0' or '1.50.0-nightly'
+    pub release: String,
+
+    /// the commit hash of the compiler, or for '$RUST_SRC_PATH'
+    /// a hash of the source directory
+    pub commit_hash: String,
+
+    /// the channel: stable, beta, nightly or dev, or 'src' for '$RUST_SRC_PATH'
+    pub channel: String
+}
+
+/// the channel of the toolchain standing for the source given by '$RUST_SRC_PATH'
+const RUST_SRC_PATH_CHANNEL: &str = "src";
+
+impl Toolchain {
+    /// The toolchain used at the start directory, so that 'rust-toolchain.toml'
+    /// files and rustup overrides are honored. 'rustc' - or '$RUSTC' - is called
+    /// with '--print sysroot' and '-vV'.
+    fn detect(config: &Config) -> Option<Toolchain> {
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let sysroot = rustc_output(Command::new(&rustc).arg("--print").arg("sysroot").current_dir(&config.start_dir))?;
+        let version = rustc_output(Command::new(&rustc).arg("-vV").current_dir(&config.start_dir))?;
+        Toolchain::from_version(PathBuf::from(sysroot.trim()), &version)
+    }
+
+    /// The toolchain standing for the source of the standard library given by
+    /// '$RUST_SRC_PATH', which is identified by `src_path` - its 'sysroot' - so
+    /// that its cached tags are independent of the installed toolchains.
+    fn from_rust_src_path(src_path: &Path) -> Toolchain {
+        let mut hasher = DefaultHasher::new();
+        src_path.hash(&mut hasher);
+        Toolchain {
+            sysroot: src_path.to_path_buf(),
+            release: format!("RUST_SRC_PATH={}", src_path.display()),
+            commit_hash: format!("{:x}", hasher.finish()),
+            channel: RUST_SRC_PATH_CHANNEL.to_string()
+        }
+    }
+
+    fn is_rust_src_path(&self) -> bool {
+        self.channel == RUST_SRC_PATH_CHANNEL
+    }
+
+    /// Parses the output of 'rustc -vV'.
+    fn from_version(sysroot: PathBuf, version: &str) -> Option<Toolchain> {
+        let field = |name: &str| {
+            version.lines()
+                .filter_map(|l| l.strip_prefix(name))
+                .map(|v| v.trim().to_string())
+                .next()
+        };
+
+        let release = field("release:")?;
+        let channel = if release.contains("-nightly") {
+            "nightly"
+        } else if release.contains("-beta") {
+            "beta"
+        } else if release.contains("-dev") {
+            "dev"
+        } else {
+            "stable"
+        };
+
+        // locally build compilers might not have a commit hash
+        let commit_hash = match field("commit-hash:") {
+            Some(ref hash) if hash != "unknown" => hash.clone(),
+            _ => {
+                let mut hasher = DefaultHasher::new();
+                release.hash(&mut hasher);
+                sysroot.hash(&mut hasher);
+                format!("{:x}", hasher.finish())
+            }
+        };
+
+        Some(Toolchain { sysroot, release, commit_hash, channel: channel.to_string() })
+    }
+
+    /// If the toolchain is still installed, if the 'rustc' of its sysroot still
+    /// reports the same commit hash, or for '$RUST_SRC_PATH' if the source exists.
+    pub fn is_installed(&self) -> bool {
+        if self.is_rust_src_path() {
+            return self.sysroot.is_dir();
+        }
+
+        let rustc = self.sysroot.join("bin").join(if cfg!(windows) { "rustc.exe" } else { "rustc" });
+        if ! rustc.is_file() {
+            return false;
+        }
+
+        rustc_output(Command::new(rustc).arg("-vV"))
+            .and_then(|version| Toolchain::from_version(self.sysroot.clone(), &version))
+            .map(|toolchain| toolchain == *self)
+            .unwrap_or(false)
+    }
+
+    /// the cached tags `name` of the toolchain created with `key`, named '{name}-{channel}-{commit hash}-{key id}.{ext}',
+    /// e.g. 'std-all' for the tags of all crates of the standard library, 'std-{crate}' for
+    /// the ones of one crate and 'rustc-dev' for the ones of the compiler crates
+    pub fn cached_tags_file(&self, name: &str, kind: TagsKind, key: &TagsKey) -> RtResult<PathBuf> {
+        let file_name = format!("{}{}.{}", self.cached_tags_prefix(name), key.id(), kind.file_extension());
+        Ok(rusty_tags_cache_dir()?.join(file_name))
+    }
+
+    /// the start of the names of the cached tags `name` of the toolchain of any configuration
+    pub fn cached_tags_prefix(&self, name: &str) -> String {
+        format!("{}-{}-{}-", name, self.channel, self.commit_hash)
+    }
+}
+
+/// the crates of the standard library visible to all crates
+const VISIBLE_CRATES: &[&str] = &["core", "alloc", "std"];
+
+/// the crate of the standard library additionally visible to proc-macro crates
+const PROC_MACRO_CRATE: &str = "proc_macro";
+
+/// the name of the tags of the compiler crates of the 'rustc-dev' component
+const RUSTC_DEV: &str = "rustc-dev";
+
+/// The cached tags of the crates of the standard library - with '--include-std' -
+/// and of the compiler crates - with '--rustc-dev' - which are included into
+/// the tags of the roots.
+pub struct StdLibTags {
+    /// the crate name - or 'rustc-dev' for the compiler
+    /// crates - the kind and the cached tags file
+    tags_files: Vec<(&'static str, TagsKind, PathBuf)>
+}
+
+impl StdLibTags {
+    /// the tags files of the crates of the standard library and of
+    /// the compiler visible to `source`, the compiler crates are
+    /// only visible to crates using the 'rustc_private' feature
+    pub fn tags_files(&self, kind: TagsKind, source: &Source) -> Vec<&Path> {
+        self.tags_files.iter()
+            .filter(|&&(name, k, _)| {
+                k == kind && (VISIBLE_CRATES.contains(&name)
+                              || (source.is_proc_macro && name == PROC_MACRO_CRATE)
+                              || (source.uses_rustc_private && name == RUSTC_DEV))
+            })
+            .map(|(_, _, file)| file.as_path())
+            .filter(|f| f.is_file())
+            .collect()
+    }
+}
+
+/// Creates the tags of the standard library, if its source is found by
+/// `find_src_dir`. The tags are cached per toolchain and are copied into
+/// the source directory or '--output-dir-std'. With '--include-std' and
+/// '--rustc-dev' the tags of the crates visible to the roots are returned.
+/// Without '$RUST_SRC_PATH' the toolchain is only searched for if the tags
+/// of the standard library or of the compiler crates are requested.
+pub fn update_std_lib_tags(config: &Config) -> RtResult<Option<StdLibTags>> {
+    let is_requested = config.include_std || config.output_dir_std.is_some() || config.rustc_dev;
+    if ! is_requested && env::var_os("RUST_SRC_PATH").is_none() {
+        return Ok(None);
+    }
+
+    let (src_path, toolchain) = match find_src_dir(config)? {
+        StdLibSrc::Found { src_path, toolchain } => (src_path, toolchain),
+
+        StdLibSrc::Missing { toolchain } => {
+            let msg = format!("Couldn't find the source of the standard library of the toolchain at '{}', \
+                               its tags aren't created. Install it by calling:\n    $ rustup component add rust-src",
+                              toolchain.sysroot.display());
+
+            // only '--rustc-dev' doesn't need the source of the standard library
+            if config.include_std || config.output_dir_std.is_some() {
+                info!(config, "{}", msg);
+            } else {
+                verbose!(config, "{}", msg);
+            }
+
+            return Ok(None);
+        }
+
+        StdLibSrc::NoToolchain => {
+            verbose!(config, "Couldn't execute 'rustc', the tags of the standard library aren't created");
+            return Ok(None);
+        }
+    };
+
+    let mut created = false;
+    let src_dirs = crate_dirs(&src_path)?;
+    if src_dirs.is_empty() {
+        return Err(format!("No crates of the standard library found in its source directory '{}'!", src_path.display()).into());
+    }
+
+    let cached_std_lib_tags = cached_tags(config, &toolchain, "std-all")?;
+    created |= update_cached_tags(config, &src_path, &toolchain, "std-all", &src_dirs, &cached_std_lib_tags)?;
+
+    match config.output_dir_std {
+        Some(ref output_dir) => copy_std_lib_tags(config, &cached_std_lib_tags, output_dir, created)?,
+
+        // the source directory of the toolchain might not be writable, e.g. if it's
+        // installed by the package manager, the tags are then only kept in the cache
+        None => {
+            if let Err(err) = copy_std_lib_tags(config, &cached_std_lib_tags, &src_path, created) {
+                verbose!(config, "Couldn't write the tags of the standard library into '{}': {}", src_path.display(), err);
+            }
+        }
+    }
+
+    let mut tags_files = Vec::new();
+    if config.include_std {
+        for name in VISIBLE_CRATES.iter().chain(&[PROC_MACRO_CRATE]) {
+            // rustc < 1.47.0 has the crates in directories named 'lib{name}'
+            let crate_dir = match [name.to_string(), format!("lib{}", name)].iter().map(|d| src_path.join(d)).find(|d| d.is_dir()) {
+                Some(crate_dir) => crate_dir,
+                None            => continue
+            };
+
+            let cache_name = format!("std-{}", name);
+            let cached_crate_tags = cached_tags(config, &toolchain, &cache_name)?;
+            created |= update_cached_tags(config, &src_path, &toolchain, &cache_name, &[crate_dir], &cached_crate_tags)?;
+            tags_files.extend(cached_crate_tags.into_iter().map(|(kind, file)| (*name, kind, file)));
+        }
+    }
+
+    // the compiler crates always belong to the toolchain used at the start directory
+    let rustc_toolchain = if ! config.rustc_dev {
+        None
+    } else if toolchain.is_rust_src_path() {
+        Toolchain::detect(config)
+    } else {
+        Some(toolchain.clone())
+    };
+
+    if let Some(toolchain) = rustc_toolchain {
+        let compiler_path = rustc_dev_src_dir(&toolchain);
+        if compiler_path.is_dir() {
+            let cached_rustc_dev_tags = cached_tags(config, &toolchain, RUSTC_DEV)?;
+            created |= update_cached_tags(config, &compiler_path, &toolchain, RUSTC_DEV, &crate_dirs(&compiler_path)?, &cached_rustc_dev_tags)?;
+            tags_files.extend(cached_rustc_dev_tags.into_iter().map(|(kind, file)| (RUSTC_DEV, kind, file)));
+        } else {
+            info!(config, "Couldn't find the source of the compiler crates of the toolchain at '{}', \
+                           their tags aren't created. Install it by calling:\n    $ rustup component add rustc-dev",
+                  toolchain.sysroot.display());
+        }
+    }
+
+    if created {
+        cache::prune_std_lib_tags(config)?;
+    }
+
+    if tags_files.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(StdLibTags { tags_files }))
+    }
+}
+
+/// Copies the cached tags of the standard library `cached_std_lib_tags` into `output_dir`,
+/// if they aren't already there. The tags in `output_dir` might be the ones of an other
+/// toolchain, so the meta data of the copied tags is stored beside of them and compared.
+fn copy_std_lib_tags(config: &Config, cached_std_lib_tags: &[(TagsKind, PathBuf)], output_dir: &Path, created: bool) -> RtResult<()> {
+    for &(kind, ref cached_tags) in cached_std_lib_tags {
+        let std_lib_tags = output_dir.join(config.tags_spec.file_name(kind));
+        let cached_info = CacheInfo::load(cached_tags);
+        let is_copied = std_lib_tags.is_file() && ! created && match (&cached_info, CacheInfo::load(&std_lib_tags)) {
+            (Some(cached_info), Some(copied_info)) => cached_info.key == copied_info.key && cached_info.toolchain == copied_info.toolchain,
+            _                                      => false
+        };
+
+        if ! is_copied {
+            let tmp_tags = TempFile::new_in(output_dir)?;
+            copy_tags(config, cached_tags, tmp_tags.path())?;
+            move_tags(config, tmp_tags.path(), &std_lib_tags)?;
+            if let Some(cached_info) = cached_info {
+                cached_info.save(&std_lib_tags)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// the source directory of the compiler crates of the 'rustc-dev' component
+fn rustc_dev_src_dir(toolchain: &Toolchain) -> PathBuf {
+    toolchain.sysroot.join("lib").join("rustlib").join("rustc-src").join("rust").join("compiler")
+}
+
+/// If one of the `crate_roots` - e.g. a 'lib.rs' or 'main.rs' - enables the 'rustc_private' feature.
+pub fn uses_rustc_private(crate_roots: &[&Path]) -> bool {
+    crate_roots.iter().any(|root| {
+        fs::read_to_string(root)
+            .map(|contents| cfg::enables_feature(&contents, "rustc_private"))
+            .unwrap_or(false)
+    })
+}
+
+/// the start of the names of all cached tags files of the standard library of `toolchain`
+pub fn all_cached_tags_prefixes(toolchain: &Toolchain) -> Vec<String> {
+    let std_crates = VISIBLE_CRATES.iter().chain(&[PROC_MACRO_CRATE, "all"]).map(|name| format!("std-{}", name));
+    std_crates.chain(Some(RUSTC_DEV.to_string()))
+        .map(|name| toolchain.cached_tags_prefix(&name))
+        .collect()
+}
+
+/// the cached tags of the crates `name` of the standard library of `toolchain` for each kind
+fn cached_tags(config: &Config, toolchain: &Toolchain, name: &str) -> RtResult<Vec<(TagsKind, PathBuf)>> {
+    let mut cached_tags = Vec::with_capacity(config.tags_spec.kinds.len());
+    for kind in &config.tags_spec.kinds {
+        cached_tags.push((*kind, toolchain.cached_tags_file(name, *kind, &config.tags_key(*kind, false))?));
+    }
+
+    Ok(cached_tags)
+}
+
+/// Creates the cached tags `cached_tags` of the crates at `src_dirs`, if they
+/// don't exist or were created with a different configuration. Returns if
+/// they were created.
+fn update_cached_tags(config: &Config,
+                      src_path: &Path,
+                      toolchain: &Toolchain,
+                      name: &str,
+                      src_dirs: &[PathBuf],
+                      cached_tags: &[(TagsKind, PathBuf)])
+                      -> RtResult<bool> {
+    let is_up_to_date = ! config.force_recreate && cached_tags.iter().all(|&(kind, ref file)| {
+        file.is_file() && CacheInfo::load(file).map(|i| i.key == config.tags_key(kind, false)).unwrap_or(false)
+    });
+
+    if is_up_to_date {
+        for (_, file) in cached_tags {
+            CacheInfo::touch(config, file);
+        }
+
+        return Ok(false);
+    }
+
+    info!(config, "Creating tags for '{}' of the toolchain {} ...", name, toolchain.release);
+
+    let cache_dir = rusty_tags_cache_dir()?;
+    let mut tmp_tags = Vec::with_capacity(cached_tags.len());
+    for &(kind, _) in cached_tags {
+        tmp_tags.push((kind, TempFile::new_in(cache_dir)?));
+    }
+
+    {
+        let tmp_tags_files: Vec<_> = tmp_tags.iter()
+            .map(|&(kind, ref file)| (kind, file.path().to_path_buf()))
+            .collect();
+
+        create_all_tags(config, src_dirs, &[], &Excludes::default(), &tmp_tags_files)?;
+        if let Some(filter) = CodeFilter::for_std_lib(config) {
+            for (kind, tags_file) in &tmp_tags_files {
+                verbose!(config, "\nRemoving the tags of excluded code from '{}'", tags_file.display());
+                filter.filter_tags(*kind, tags_file)?;
+            }
+        }
+    }
+
+    for ((kind, tmp_tags), (_, cached_tags)) in tmp_tags.iter().zip(cached_tags) {
+        move_tags(config, tmp_tags.path(), cached_tags)?;
+        CacheInfo::new_std_lib(name, config.tags_key(*kind, false), src_path, toolchain).save(cached_tags)?;
+    }
+
+    Ok(true)
+}
+
+/// Finds the source of the standard library at `$RUST_SRC_PATH` or - if
+/// it isn't defined - in the sysroot of the toolchain used at the start
+/// directory.
+pub fn find_src_dir(config: &Config) -> RtResult<StdLibSrc> {
+    if let Some(src_path) = env::var_os("RUST_SRC_PATH") {
+        let src_path = PathBuf::from(src_path);
+        if ! src_path.is_dir() {
+            return Err(format!("Missing rust source code at '{}'!", src_path.display()).into());
+        }
+
+        let toolchain = Toolchain::from_rust_src_path(&src_path);
+        return Ok(StdLibSrc::Found { src_path, toolchain });
+    }
+
+    let toolchain = match Toolchain::detect(config) {
+        Some(toolchain) => toolchain,
+        None            => return Ok(StdLibSrc::NoToolchain)
+    };
+
+    let rust_src = toolchain.sysroot.join("lib").join("rustlib").join("src").join("rust");
+
+    // rustc >= 1.47.0 has the crates in 'library', older ones in 'src'
+    for dir in &["library", "src"] {
+        let src_path = rust_src.join(dir);
+        if src_path.is_dir() {
+            return Ok(StdLibSrc::Found { src_path, toolchain });
+        }
+    }
+
+    Ok(StdLibSrc::Missing { toolchain })
+}
+
+/// The trimmed stdout of a successful `cmd`.
+fn rustc_output(cmd: &mut Command) -> Option<String> {
+    match cleanup::run(cmd) {
+        Ok(ref output) if output.status.success() => {
+            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if stdout.is_empty() { None } else { Some(stdout) }
+        }
+
+        _ => None
+    }
+}
+
+/// The directories of the crates of the standard library inside of `src_path`, which
+/// are recognized by a 'Cargo.toml', 'lib.rs' or 'src/lib.rs' file, sorted by name.
+fn crate_dirs(src_path: &Path) -> RtResult<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(src_path)? {
+        let dir = entry?.path();
+        let is_hidden = dir.file_name().map(|n| n.to_string_lossy().starts_with('.')).unwrap_or(true);
+        if is_hidden || ! dir.is_dir() {
+            continue;
+        }
+
+        let is_crate = ["Cargo.toml", "lib.rs", "src/lib.rs"].iter().any(|f| dir.join(f).is_file());
+        if is_crate {
+            dirs.push(dir);
+        }
+    }
+
+    dirs.sort();
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+    use semver::Version;
+    use tempfile::TempDir;
+    use types::{DepTree, SourceKind};
+    use super::*;
+
+    const STABLE_VERSION: &str = "rustc 1.47.0 (18bf6b4f0 2020-10-07)\n\
+                                  binary: rustc\n\
+                                  commit-hash: 18bf6b4f01a6feaf7259ba7cdae58031af1b7b39\n\
+                                  commit-date: 2020-10-07\n\
+                                  host: x86_64-unknown-linux-gnu\n\
+                                  release: 1.47.0\n\
+                                  LLVM version: 11.0\n";
+
+    #[test]
+    fn toolchain_from_version() {
+        let sysroot = PathBuf::from("/home/user/.rustup/toolchains/stable-x86_64-unknown-linux-gnu");
+        let toolchain = Toolchain::from_version(sysroot.clone(), STABLE_VERSION).unwrap();
+        assert_eq!(toolchain, Toolchain {
+            sysroot: sysroot.clone(),
+            release: "1.47.0".to_string(),
+            commit_hash: "18bf6b4f01a6feaf7259ba7cdae58031af1b7b39".to_string(),
+            channel: "stable".to_string()
+        });
+
+        let nightly = Toolchain::from_version(sysroot.clone(), "commit-hash: abc\nrelease: 1.50.0-nightly\n").unwrap();
+        assert_eq!((nightly.channel.as_str(), nightly.commit_hash.as_str()), ("nightly", "abc"));
+        let beta = Toolchain::from_version(sysroot.clone(), "commit-hash: abc\nrelease: 1.49.0-beta.2\n").unwrap();
+        assert_eq!(beta.channel, "beta");
+
+        // locally build compilers are identified by their release and sysroot
+        let dev = Toolchain::from_version(sysroot.clone(), "commit-hash: unknown\nrelease: 1.51.0-dev\n").unwrap();
+        let other_dev = Toolchain::from_version(PathBuf::from("/opt/rust"), "commit-hash: unknown\nrelease: 1.51.0-dev\n").unwrap();
+        assert_eq!(dev.channel, "dev");
+        assert!(dev.commit_hash != "unknown" && dev.commit_hash != other_dev.commit_hash);
+
+        assert!(Toolchain::from_version(sysroot, "error: no such command\n").is_none());
+    }
+
+    #[test]
+    fn cached_tags_names_of_toolchain() {
+        let toolchain = Toolchain::from_version(PathBuf::from("/sysroot"), STABLE_VERSION).unwrap();
+        assert_eq!(toolchain.cached_tags_prefix("std-all"), "std-all-stable-18bf6b4f01a6feaf7259ba7cdae58031af1b7b39-");
+        let names: Vec<_> = all_cached_tags_prefixes(&toolchain).iter()
+            .map(|p| p.trim_end_matches("-stable-18bf6b4f01a6feaf7259ba7cdae58031af1b7b39-").to_string())
+            .collect();
+
+        assert_eq!(names, vec!["std-core", "std-alloc", "std-std", "std-proc_macro", "std-all", "rustc-dev"]);
+
+        // the tags of '$RUST_SRC_PATH' are cached per source directory
+        let src = Toolchain::from_rust_src_path(Path::new("/src/rust/library"));
+        let other_src = Toolchain::from_rust_src_path(Path::new("/other/rust/library"));
+        assert!(src.cached_tags_prefix("std-all").starts_with("std-all-src-"));
+        assert!(src.cached_tags_prefix("std-all") != other_src.cached_tags_prefix("std-all"));
+        assert!(! src.is_installed());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn installed_toolchains() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let sysroot = dir.path().to_path_buf();
+        let toolchain = Toolchain::from_version(sysroot.clone(), STABLE_VERSION).unwrap();
+        assert!(! toolchain.is_installed());
+
+        let rustc = |version: &str| {
+            let rustc = sysroot.join("bin").join("rustc");
+            fs::create_dir_all(rustc.parent().unwrap()).unwrap();
+            let mut file = File::create(&rustc).unwrap();
+            write!(file, "#!/bin/sh\nprintf '{}'\n", version.replace('\n', "\\n")).unwrap();
+            fs::set_permissions(&rustc, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+
+        rustc(STABLE_VERSION);
+        assert!(toolchain.is_installed());
+
+        // e.g. updated by 'rustup update'
+        rustc(&STABLE_VERSION.replace("18bf6b4f01a6feaf7259ba7cdae58031af1b7b39", "7eac88abb2e57e752f3302f02be5f3ce3d7adfb4"));
+        assert!(! toolchain.is_installed());
+    }
+
+    #[test]
+    fn std_lib_tags_visible_to_sources() {
+        let dir = TempDir::new().unwrap();
+        let config = Config::for_tests(dir.path());
+        let tags_file = |name: &str, kind: TagsKind| {
+            let path = dir.path().join(format!("{}.{}", name, kind.file_extension()));
+            File::create(&path).unwrap();
+            path
+        };
+
+        let std_lib_tags = StdLibTags {
+            tags_files: vec![("core", TagsKind::Vi, tags_file("core", TagsKind::Vi)),
+                             ("core", TagsKind::Emacs, tags_file("core", TagsKind::Emacs)),
+                             ("std", TagsKind::Vi, tags_file("std", TagsKind::Vi)),
+                             ("proc_macro", TagsKind::Vi, tags_file("proc_macro", TagsKind::Vi)),
+                             ("rustc-dev", TagsKind::Vi, tags_file("rustc-dev", TagsKind::Vi)),
+                             // e.g. the crate isn't part of the source of the standard library
+                             ("alloc", TagsKind::Vi, dir.path().join("alloc.vi"))]
+        };
+
+        let mut source = Source::new(DepTree::new().new_source(), "app", &Version::new(0, 1, 0),
+                                     dir.path(), SourceKind::Path, true, &config).unwrap();
+
+        let names = |source: &Source, kind: TagsKind| -> Vec<String> {
+            std_lib_tags.tags_files(kind, source).iter()
+                .map(|f| f.file_stem().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(names(&source, TagsKind::Vi), vec!["core", "std"]);
+        assert_eq!(names(&source, TagsKind::Emacs), vec!["core"]);
+
+        source.is_proc_macro = true;
+        assert_eq!(names(&source, TagsKind::Vi), vec!["core", "std", "proc_macro"]);
+
+        source.is_proc_macro = false;
+        source.uses_rustc_private = true;
+        assert_eq!(names(&source, TagsKind::Vi), vec!["core", "std", "rustc-dev"]);
+    }
+
+    #[test]
+    fn std_lib_crate_dirs() {
+        let dir = TempDir::new().unwrap();
+        let src_path = dir.path();
+        for (crate_dir, file) in &[("std", "Cargo.toml"), ("core", "src/lib.rs"), ("libc", "lib.rs"), ("stdarch", "README.md"), (".git", "Cargo.toml")] {
+            let path = src_path.join(crate_dir).join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+
+        File::create(src_path.join("Cargo.toml")).unwrap();
+        assert_eq!(crate_dirs(src_path).unwrap(), vec![src_path.join("core"), src_path.join("libc"), src_path.join("std")]);
+    }
+}
@@ -1,18 +1,26 @@
-use std::fs::{File, OpenOptions, copy, rename};
-use std::io::{Read, Write, BufWriter};
-use std::path::Path;
+use std::fs::{File, OpenOptions, copy, create_dir_all, remove_file, rename};
+use std::io::{self, Read, Write, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-use tempfile::NamedTempFile;
+use cleanup::{self, TempFile};
 use scoped_threadpool::Pool;
 use fnv::FnvHashSet;
 
-use rt_result::RtResult;
-use types::{TagsKind, SourceWithTmpTags, Sources, DepTree, unique_sources};
+use rt_result::{RtErr, RtResult};
+use types::{TagsKind, TagsOutput, Source, SourceWithTmpTags, Sources, DepTree, unique_sources};
 use config::Config;
-use dirs::rusty_tags_cache_dir;
-
-/// Update the tags of all sources in 'dep_tree'
-pub fn update_tags(config: &Config, dep_tree: &DepTree) -> RtResult<()> {
+use dirs::{rusty_tags_cache_dir, mirrored_dir};
+use etags;
+use cache::{self, CacheInfo};
+use std_lib::StdLibTags;
+use files::{self, Excludes};
+use glob;
+use cfg::CodeFilter;
+
+/// Update the tags of all sources in 'dep_tree', the tags of the roots
+/// include the tags of the standard library `std_lib_tags`
+pub fn update_tags(config: &Config, dep_tree: &DepTree, std_lib_tags: Option<&StdLibTags>) -> RtResult<()> {
     if ! config.quiet {
         let names: Vec<_> = dep_tree.roots().map(|r| &r.name).collect();
         let num_names = names.len();
@@ -27,7 +35,11 @@ pub fn update_tags(config: &Config, dep_tree: &DepTree) -> RtResult<()> {
         print!(" ...\n");
     }
 
-    let sources_to_update: Vec<_> = dep_tree.all_sources().filter(|s| {
+    // only the selected roots and their dependencies are considered
+    let reachable_sources = dep_tree.reachable_sources();
+    let reachable_ids: FnvHashSet<_> = reachable_sources.iter().map(|s| s.id).collect();
+
+    let sources_to_update: Vec<_> = reachable_sources.iter().cloned().filter(|s| {
         s.needs_tags_update(config)
     })
     .collect();
@@ -36,9 +48,18 @@ pub fn update_tags(config: &Config, dep_tree: &DepTree) -> RtResult<()> {
     // dependent (ancestor) sources also have to be updated. The reason for the missing tags
     // might be a version change of the source - by changes in the 'Cargo.toml' - so all
     // dependent sources have to be rebuild to include the new version.
+    //
+    // If only tags lists are created, then the tags of a source don't contain
+    // the tags of its dependencies, and the lists of the roots are always recreated.
     let sources_to_update = {
-        let mut srcs = dep_tree.ancestors(&sources_to_update);
+        let mut srcs = if config.tags_list {
+            Vec::new()
+        } else {
+            dep_tree.ancestors(&sources_to_update)
+        };
+
         srcs.extend(&sources_to_update);
+        srcs.retain(|s| reachable_ids.contains(&s.id));
         unique_sources(&mut srcs);
 
         // sort the sources by their depth in the dependency tree to ensure that
@@ -69,45 +90,161 @@ pub fn update_tags(config: &Config, dep_tree: &DepTree) -> RtResult<()> {
 
     // Create the tags for each source in 'sources_to_update'. This creates
     // only the tags of the source without considering the dependencies.
+    let failed_sources = Mutex::new(Vec::new());
     if let Some(ref mut thread_pool) = thread_pool {
+        let failed_sources = &failed_sources;
         thread_pool.scoped(|scoped| {
-            for &SourceWithTmpTags { ref source, ref tags_file, .. } in &sources_to_update {
+            for &SourceWithTmpTags { source, ref tags_files, .. } in &sources_to_update {
                 scoped.execute(move || {
-                    create_tags(config, &[&source.dir], tags_file.as_path()).unwrap();
+                    if let Err(err) = create_source_tags(config, source, tags_files) {
+                        failed_sources.lock().unwrap().push((source, err));
+                    }
                 });
             }
         });
     } else {
-        for &SourceWithTmpTags { ref source, ref tags_file, .. } in &sources_to_update {
-            create_tags(config, &[&source.dir], tags_file.as_path())?;
+        for &SourceWithTmpTags { source, ref tags_files, .. } in &sources_to_update {
+            if let Err(err) = create_source_tags(config, source, tags_files) {
+                failed_sources.lock().unwrap().push((source, err));
+                if ! config.keep_going {
+                    break;
+                }
+            }
         }
     }
 
+    // In keep-going mode the failed sources are skipped, their
+    // tags are created again by the next run of rusty-tags.
+    let failed_sources = failed_sources.into_inner().unwrap();
+    report_failed_sources(config, &failed_sources)?;
+
+    let failed_ids: FnvHashSet<_> = failed_sources.iter().map(|&(s, _)| s.id).collect();
+    let sources_to_update: Vec<_> = sources_to_update.into_iter()
+        .filter(|s| ! failed_ids.contains(&s.source.id))
+        .collect();
+
     // Creates the cacheable tags of each source in 'sources_to_update'. The cacheable
     // tags contain the tags of the source and the tags of the public exported dependencies.
     // Furthermore creates the final tags of each source in 'sources_to_update'. The
     // final tags contain the tags of the source and of all direct dependencies.
+    let failed_sources = Mutex::new(Vec::new());
     if let Some(ref mut thread_pool) = thread_pool {
+        let failed_sources = &failed_sources;
         thread_pool.scoped(|scoped| {
             for src in &sources_to_update {
                 scoped.execute(move || {
-                    let deps = dep_tree.dependencies(src.source);
-                    update_tags_internal(config, src, deps).unwrap();
+                    if let Err(err) = update_tags_internal(config, src, dep_tree, std_lib_tags) {
+                        failed_sources.lock().unwrap().push((src.source, err));
+                    }
                 });
             }
         });
     } else {
         for src in &sources_to_update {
-            let deps = dep_tree.dependencies(src.source);
-            update_tags_internal(config, src, deps)?;
+            if let Err(err) = update_tags_internal(config, src, dep_tree, std_lib_tags) {
+                failed_sources.lock().unwrap().push((src.source, err));
+                if ! config.keep_going {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The cached tags of the failed sources might already be written, so their
+    // meta data is removed to get their tags created again by the next run.
+    let failed_sources = failed_sources.into_inner().unwrap();
+    for &(source, _) in &failed_sources {
+        for files in &source.tags_files {
+            let _ = remove_file(cache::info_file(&files.cached_tags_file));
+        }
+    }
+
+    report_failed_sources(config, &failed_sources)?;
+
+    // record the usage of the cached tags of the not updated sources,
+    // the ones of the updated sources got new meta data
+    let updated_ids: FnvHashSet<_> = sources_to_update.iter().map(|s| s.source.id).collect();
+    for source in reachable_sources.iter().filter(|s| ! updated_ids.contains(&s.id)) {
+        for files in &source.tags_files {
+            CacheInfo::touch(config, &files.cached_tags_file);
         }
     }
 
     return Ok(());
 
-    fn update_tags_internal<'a>(config: &Config, source_with_tags: &SourceWithTmpTags<'a>, dependencies: Sources<'a>) -> RtResult<()> {
+    fn update_tags_internal<'a>(config: &Config, source_with_tags: &SourceWithTmpTags<'a>, dep_tree: &'a DepTree, std_lib_tags: Option<&StdLibTags>) -> RtResult<()> {
         let source = source_with_tags.source;
-        let tmp_src_tags = source_with_tags.tags_file.as_path();
+        for kind in &config.tags_spec.kinds {
+            let std_lib_tags_files = match std_lib_tags {
+                Some(std_lib_tags) if source.is_root => std_lib_tags.tags_files(*kind, source),
+                _                                    => Vec::new()
+            };
+
+            if config.tags_list {
+                update_listed_tags(config, *kind, source_with_tags, dep_tree, &std_lib_tags_files)?;
+            } else {
+                let deps = dep_tree.dependencies(source);
+                update_tags_of_kind(config, *kind, source_with_tags, deps, &std_lib_tags_files)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Only the tags of the source itself are cached. For roots the tags
+    /// file only contains the tags of the root and a list of the tags files
+    /// of all direct and indirect dependencies is created. For vi the list
+    /// is written into a vim script beside of the tags file, for emacs the
+    /// tags files are included into the tags file.
+    fn update_listed_tags<'a>(config: &Config, kind: TagsKind, source_with_tags: &SourceWithTmpTags<'a>, dep_tree: &'a DepTree, std_lib_tags_files: &[&Path]) -> RtResult<()> {
+        let source = source_with_tags.source;
+        let src_tags_files = source.tags_files(kind);
+        let tmp_src_tags = source_with_tags.tags_file(kind);
+
+        {
+            let tmp_cached_tags = TempFile::new_in(rusty_tags_cache_dir()?)?;
+            copy_tags(config, tmp_src_tags, tmp_cached_tags.path())?;
+            move_tags(config, tmp_cached_tags.path(), &src_tags_files.cached_tags_file)?;
+            CacheInfo::new(config, source, kind)?.save(&src_tags_files.cached_tags_file)?;
+        }
+
+        if ! source.is_root {
+            return Ok(());
+        }
+
+        let mut dep_tags_files: Vec<&Path> = dep_tree.all_dependencies(source)
+            .iter()
+            .map(|d| d.tags_files(kind).cached_tags_file.as_path())
+            .filter(|f| f.is_file())
+            .collect();
+
+        dep_tags_files.extend(std_lib_tags_files);
+
+        let (tmp_root_tags, tags_file) = temp_tags_file(config, source, &src_tags_files.tags_file)?;
+        match kind {
+            TagsKind::Vi => {
+                copy_tags(config, tmp_src_tags, tmp_root_tags.path())?;
+
+                let mut tags_files = vec![tags_file.as_path()];
+                tags_files.extend(&dep_tags_files);
+                let (tmp_tags_list, tags_list_file) = temp_tags_file(config, source, &vim_tags_list_file(&tags_file))?;
+                write_vim_tags_list(&source.name, &tags_files, tmp_tags_list.path())?;
+                move_tags(config, tmp_tags_list.path(), &tags_list_file)?;
+            }
+
+            TagsKind::Emacs => {
+                merge_tags(config, kind, tmp_src_tags, &dep_tags_files, tmp_root_tags.path())?;
+            }
+        }
+
+        move_tags(config, tmp_root_tags.path(), &tags_file)?;
+        Ok(())
+    }
+
+    fn update_tags_of_kind<'a>(config: &Config, kind: TagsKind, source_with_tags: &SourceWithTmpTags<'a>, dependencies: Sources<'a>, std_lib_tags_files: &[&Path]) -> RtResult<()> {
+        let source = source_with_tags.source;
+        let src_tags_files = source.tags_files(kind);
+        let tmp_src_tags = source_with_tags.tags_file(kind);
 
         // create the cached tags file of 'source' which
         // might also contain the tags of dependencies if they're
@@ -128,67 +265,339 @@ pub fn update_tags(config: &Config, dep_tree: &DepTree) -> RtResult<()> {
             let reexported_tags_files: Vec<&Path> = dependencies.clone()
                 .filter(|d| reexported_crates.iter().find(|c| **c == d.name) != None)
                 .filter_map(|d| {
-                    if d.cached_tags_file.is_file() {
-                        Some(d.cached_tags_file.as_path())
+                    let cached_tags_file = &d.tags_files(kind).cached_tags_file;
+                    if cached_tags_file.is_file() {
+                        Some(cached_tags_file.as_path())
                     } else {
                         verbose!(config, "\nCouldn't find tags file '{}' of reexported crate. Might be a cyclic dependency?",
-                                 d.cached_tags_file.display());
+                                 cached_tags_file.display());
                         None
                     }
                 })
                 .collect();
 
-            let tmp_cached_tags = NamedTempFile::new_in(rusty_tags_cache_dir()?)?;
+            let tmp_cached_tags = TempFile::new_in(rusty_tags_cache_dir()?)?;
             if ! reexported_tags_files.is_empty() {
-                merge_tags(config, tmp_src_tags, &reexported_tags_files, tmp_cached_tags.path())?;
+                merge_tags(config, kind, tmp_src_tags, &reexported_tags_files, tmp_cached_tags.path())?;
             } else {
                 copy_tags(config, tmp_src_tags, tmp_cached_tags.path())?;
             }
 
-            move_tags(config, tmp_cached_tags.path(), &source.cached_tags_file)?;
+            move_tags(config, tmp_cached_tags.path(), &src_tags_files.cached_tags_file)?;
+            CacheInfo::new(config, source, kind)?.save(&src_tags_files.cached_tags_file)?;
         }
 
-        // create the source tags file of 'source' by merging
-        // the tags of 'source' and of its dependencies
+        // create the source tags file of 'source' by merging the tags
+        // of 'source', of its dependencies and of the standard library
         {
-            let dep_tags_files: Vec<&Path> = dependencies.clone()
+            let mut dep_tags_files: Vec<&Path> = dependencies.clone()
                 .filter_map(|d| {
-                    if d.cached_tags_file.is_file() {
-                        Some(d.cached_tags_file.as_path())
+                    let cached_tags_file = &d.tags_files(kind).cached_tags_file;
+                    if cached_tags_file.is_file() {
+                        Some(cached_tags_file.as_path())
                     } else {
                         verbose!(config, "\nCouldn't find tags file '{}' of dependency. Might be a cyclic dependency?",
-                                 d.cached_tags_file.display());
+                                 cached_tags_file.display());
                         None
                     }
                 })
                 .collect();
 
-            let tmp_src_and_dep_tags = NamedTempFile::new_in(&source.dir)?;
+            dep_tags_files.extend(std_lib_tags_files);
+
+            let (tmp_src_and_dep_tags, tags_file) = temp_tags_file(config, source, &src_tags_files.tags_file)?;
             if ! dep_tags_files.is_empty() {
-                merge_tags(config, tmp_src_tags, &dep_tags_files, tmp_src_and_dep_tags.path())?;
+                merge_tags(config, kind, tmp_src_tags, &dep_tags_files, tmp_src_and_dep_tags.path())?;
             } else {
                 copy_tags(config, tmp_src_tags, tmp_src_and_dep_tags.path())?;
             }
 
-            move_tags(config, tmp_src_and_dep_tags.path(), &source.tags_file)?;
+            move_tags(config, tmp_src_and_dep_tags.path(), &tags_file)?;
         }
 
         Ok(())
     }
 }
 
-/// creates tags recursive for the directory hierarchies starting at `src_dirs`
-/// and writes them to `tags_file`
-pub fn create_tags<P1, P2>(config: &Config, src_dirs: &[P1], tags_file: P2) -> RtResult<()>
+/// Creates the temporary file, which is moved to the returned path of the tags file `tags_file`
+/// of `source`. If the directory of `tags_file` isn't writable - e.g. the source directory of a
+/// dependency in a read-only registry - the tags file is written into the mirror directory.
+fn temp_tags_file(config: &Config, source: &Source, tags_file: &Path) -> RtResult<(TempFile, PathBuf)> {
+    let tags_dir = tags_file.parent()
+        .ok_or(format!("Couldn't get directory of tags file '{}'", tags_file.display()))?;
+
+    // the tags dir might be inside of the mirror directory
+    if ! tags_dir.is_dir() {
+        create_dir_all(tags_dir)?;
+    }
+
+    match TempFile::create_in(tags_dir) {
+        Ok(tmp_tags) => Ok((tmp_tags, tags_file.to_path_buf())),
+        Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            let mirrored_tags_dir = mirrored_dir(tags_dir)?;
+            verbose!(config, "Source directory '{}' of {} isn't writable, using '{}' for its tags",
+                     tags_dir.display(), source.name, mirrored_tags_dir.display());
+
+            create_dir_all(&mirrored_tags_dir)?;
+            let mirrored_tags_file = mirrored_tags_dir.join(tags_file.file_name().unwrap_or_default());
+            Ok((TempFile::new_in(&mirrored_tags_dir)?, mirrored_tags_file))
+        }
+
+        Err(err) => Err(err.into())
+    }
+}
+
+/// Returns the error of the first of the `failed_sources`, or - in keep-going
+/// mode - reports all of them, which are then skipped.
+fn report_failed_sources(config: &Config, failed_sources: &[(&Source, RtErr)]) -> RtResult<()> {
+    if failed_sources.is_empty() {
+        return Ok(());
+    }
+
+    if ! config.keep_going {
+        let (source, ref err) = failed_sources[0];
+        return Err(format!("Couldn't create the tags for '{}': {}", source.name, err.to_string().trim_end()).into());
+    }
+
+    for &(source, ref err) in failed_sources {
+        writeln!(&mut io::stderr(), "Skipping the tags for '{}' ({}): {}", source.name, source.dir.display(), err.to_string().trim_end())?;
+    }
+
+    Ok(())
+}
+
+/// Creates the tags at the workspace root - or at the '--output' path - containing
+/// the tags of all workspace members and their dependencies, by merging the tags
+/// files of the workspace members. Returns the paths of the created tags files.
+pub fn update_workspace_tags(config: &Config, dep_tree: &DepTree) -> RtResult<Vec<PathBuf>> {
+    let workspace_root = dep_tree.workspace_root()
+        .ok_or("Couldn't find the workspace root of the cargo project!")?;
+
+    let mut workspace_tags_files = Vec::with_capacity(config.tags_spec.kinds.len());
+    for kind in &config.tags_spec.kinds {
+        let kind = *kind;
+        let member_tags_files: Vec<&Path> = dep_tree.members()
+            .map(|m| m.tags_files(kind).tags_file.as_path())
+            .filter(|f| f.is_file())
+            .collect();
+
+        if member_tags_files.is_empty() {
+            continue;
+        }
+
+        let tags_file = match config.output {
+            Some(TagsOutput::File(ref path)) => path.clone(),
+            Some(TagsOutput::Stdout)         => config.temp_file(&format!("workspace.{}", kind.file_extension()))?,
+            None                             => workspace_root.join(config.tags_spec.file_name(kind))
+        };
+
+        // In a single crate project the workspace root is the directory of the root package,
+        // whose tags file then already contains the tags of the whole workspace. With multiple
+        // members a root package at the workspace root has its own member tags file.
+        if member_tags_files == [tags_file.as_path()] {
+            verbose!(config, "\nThe tags of the only workspace member are the workspace tags at '{}'", tags_file.display());
+            workspace_tags_files.push(tags_file);
+            continue;
+        }
+
+        let tags_dir = tags_file.parent()
+            .ok_or(format!("Couldn't get directory of tags file '{}'", tags_file.display()))?;
+
+        verbose!(config, "\nCreating workspace tags at '{}'", tags_file.display());
+
+        let tmp_tags = TempFile::new_in(tags_dir)?;
+        match kind {
+            TagsKind::Vi => {
+                merge_tags(config, kind, member_tags_files[0], &member_tags_files[1..], tmp_tags.path())?;
+                if config.tags_list {
+                    let mut deps: Vec<&Source> = dep_tree.members()
+                        .flat_map(|m| dep_tree.all_dependencies(m))
+                        .filter(|d| ! d.is_root)
+                        .collect();
+
+                    unique_sources(&mut deps);
+
+                    let mut tags_files = vec![tags_file.as_path()];
+                    tags_files.extend(deps.iter()
+                        .map(|d| d.tags_files(kind).cached_tags_file.as_path())
+                        .filter(|f| f.is_file()));
+
+                    let tmp_tags_list = TempFile::new_in(tags_dir)?;
+                    write_vim_tags_list("workspace", &tags_files, tmp_tags_list.path())?;
+                    move_tags(config, tmp_tags_list.path(), &vim_tags_list_file(&tags_file))?;
+                }
+            }
+
+            TagsKind::Emacs => {
+                etags::write_merged(&member_tags_files, tmp_tags.path(), config.inline_includes)?;
+            }
+        }
+
+        move_tags(config, tmp_tags.path(), &tags_file)?;
+        workspace_tags_files.push(tags_file);
+    }
+
+    Ok(workspace_tags_files)
+}
+
+/// the vim script containing the list of tags files for the vi tags file `tags_file`
+pub fn vim_tags_list_file(tags_file: &Path) -> PathBuf {
+    let mut file_name = tags_file.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".vim");
+    tags_file.with_file_name(file_name)
+}
+
+/// writes a vim script into `into_file` which sets the 'tags' option to `tags_files`
+fn write_vim_tags_list(source_name: &str, tags_files: &[&Path], into_file: &Path) -> RtResult<()> {
+    let mut file = BufWriter::new(File::create(into_file)?);
+    file.write_fmt(format_args!("\" tags files of '{}', created by rusty-tags\n", source_name))?;
+
+    let tags: Vec<String> = tags_files.iter()
+        .map(|f| {
+            f.to_string_lossy()
+                .replace('\\', "\\\\")
+                .replace(' ', "\\ ")
+                .replace(',', "\\\\,")
+                .replace('|', "\\|")
+                .replace('"', "\\\"")
+        })
+        .collect();
+
+    file.write_fmt(format_args!("setlocal tags={}\n", tags.join(",")))?;
+    Ok(())
+}
+
+/// creates tags of all kinds in `tags_files` for `source`, without the tags
+/// of its excluded code, see 'CodeFilter'
+fn create_source_tags(config: &Config, source: &Source, tags_files: &[(TagsKind, PathBuf)]) -> RtResult<()> {
+    create_all_tags(config, &source.src_dirs(), &source.extra_languages, &source.excludes(config), tags_files)?;
+    if let Some(filter) = CodeFilter::for_source(config, source) {
+        for (kind, tags_file) in tags_files {
+            verbose!(config, "\nRemoving the tags of excluded code from '{}'", tags_file.display());
+            filter.filter_tags(*kind, tags_file)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// creates tags of all kinds in `tags_files` recursive for the directory
+/// hierarchies starting at `src_dirs` without the `excludes`, of Rust and the
+/// `extra_languages`, ctags is only executed once and the tags of the other
+/// kinds are converted from its output, if ctags supports line numbers
+pub fn create_all_tags<P>(config: &Config,
+                          src_dirs: &[P],
+                          extra_languages: &[String],
+                          excludes: &Excludes,
+                          tags_files: &[(TagsKind, PathBuf)])
+                          -> RtResult<()>
+    where P: AsRef<Path>
+{
+    // with excludes the files are collected by rusty-tags
+    // and given to ctags as a list, instead of the directories
+    let files_list = if excludes.is_empty() {
+        None
+    } else {
+        let mut files = files::collect_files(src_dirs, excludes)?;
+
+        let target_dirs = package_target_dirs(src_dirs);
+        files.retain(|file| ! target_dirs.iter().any(|dir| file.starts_with(dir)));
+
+        // only the files of the tagged languages are given to ctags
+        if let Some(patterns) = config.tags_spec.file_patterns(extra_languages) {
+            files.retain(|file| {
+                let name = file.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+                patterns.iter().any(|p| glob::matches(p, &name))
+            });
+        }
+
+        let list_name = tags_files.first()
+            .and_then(|(_, file)| file.file_name())
+            .map(|name| format!("{}.files", name.to_string_lossy()))
+            .ok_or("Expected a tags file for the creation of the tags!")?;
+
+        let list_file = config.temp_file(&list_name)?;
+        let mut contents = String::new();
+        for file in &files {
+            contents.push_str(&file.to_string_lossy());
+            contents.push('\n');
+        }
+
+        File::create(&list_file)?.write_all(contents.as_bytes())?;
+        verbose!(config, "\nCollected {} files without the excluded ones into '{}'", files.len(), list_file.display());
+        Some(list_file)
+    };
+
+    let files_list = files_list.as_deref();
+    // without line numbers the tags can't be
+    // converted, so ctags is run for each kind
+    if tags_files.len() == 1 || ! config.tags_spec.needs_line_numbers() {
+        for &(kind, ref tags_file) in tags_files {
+            create_tags(config, kind, src_dirs, files_list, extra_languages, tags_file)?;
+        }
+
+        return Ok(());
+    }
+
+    let vi_tags = tags_files.iter()
+        .find(|&&(kind, _)| kind == TagsKind::Vi)
+        .map(|(_, file)| file)
+        .ok_or("Expected vi tags for the creation of multiple kinds of tags!")?;
+
+    create_tags(config, TagsKind::Vi, src_dirs, files_list, extra_languages, vi_tags)?;
+
+    for &(kind, ref tags_file) in tags_files {
+        match kind {
+            TagsKind::Vi => {}
+
+            TagsKind::Emacs => {
+                verbose!(config, "\nConverting tags ...\n   from:\n      {}\n   to:\n      {}",
+                         vi_tags.display(), tags_file.display());
+
+                etags::convert_from_vi(vi_tags, tags_file)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The target directories of the package directories in `src_dirs` - e.g. of a
+/// binary beside of the 'Cargo.toml' or of a package tagged with extra languages -
+/// which contain build artifacts and aren't tagged.
+fn package_target_dirs<P: AsRef<Path>>(src_dirs: &[P]) -> Vec<PathBuf> {
+    src_dirs.iter()
+        .map(AsRef::as_ref)
+        .filter(|dir| dir.join("Cargo.toml").is_file())
+        .map(|dir| dir.join("target"))
+        .collect()
+}
+
+/// creates tags of `kind` recursive for the directory hierarchies starting
+/// at `src_dirs` - or for the files listed in `files_list` - of Rust and the
+/// `extra_languages`, and writes them to `tags_file`
+pub fn create_tags<P1, P2>(config: &Config,
+                           kind: TagsKind,
+                           src_dirs: &[P1],
+                           files_list: Option<&Path>,
+                           extra_languages: &[String],
+                           tags_file: P2)
+                           -> RtResult<()>
     where P1: AsRef<Path>,
           P2: AsRef<Path>
 {
-    let mut cmd = config.tags_spec.ctags_command();
+    let exclude_target = ! package_target_dirs(src_dirs).is_empty();
+    let mut cmd = config.tags_spec.ctags_command(kind, extra_languages, exclude_target)?;
     cmd.arg("-o")
        .arg(tags_file.as_ref());
 
-    for dir in src_dirs {
-        cmd.arg(dir.as_ref());
+    match files_list {
+        Some(files_list) => { cmd.arg("-L").arg(files_list); }
+        None => {
+            for dir in src_dirs {
+                cmd.arg(dir.as_ref());
+            }
+        }
     }
 
     if config.verbose {
@@ -202,8 +611,15 @@ pub fn create_tags<P1, P2>(config: &Config, src_dirs: &[P1], tags_file: P2) -> R
         println!("\n   cached at:\n      {}", tags_file.as_ref().display());
     }
 
-    let output = cmd.output()
-        .map_err(|err| format!("'ctags' execution failed: {}\nIs 'ctags' correctly installed?", err))?;
+    let output = cleanup::run_with_timeout(&mut cmd, config.ctags_timeout).map_err(|err| {
+        if err.kind() == io::ErrorKind::TimedOut {
+            let dirs: Vec<_> = src_dirs.iter().map(|d| format!("'{}'", d.as_ref().display())).collect();
+            format!("'ctags' timed out after {}s creating the tags for {}",
+                    config.ctags_timeout.map(|t| t.as_secs()).unwrap_or(0), dirs.join(", "))
+        } else {
+            format!("'ctags' execution failed: {}\nIs 'ctags' correctly installed?", err)
+        }
+    })?;
 
     if ! output.status.success() {
         let mut msg = String::from_utf8_lossy(&output.stderr).into_owned();
@@ -240,6 +656,7 @@ pub fn move_tags(config: &Config, from_tags: &Path, to_tags: &Path) -> RtResult<
 /// merges the library tag file `lib_tag_file` and its dependency tag files
 /// `dependency_tag_files` into `into_tag_file`
 fn merge_tags(config: &Config,
+              kind: TagsKind,
               lib_tag_file: &Path,
               dependency_tag_files: &[&Path],
               into_tag_file: &Path)
@@ -253,7 +670,7 @@ fn merge_tags(config: &Config,
         println!("\n   into:\n      {}", into_tag_file.display());
     }
 
-    match config.tags_spec.kind {
+    match kind {
         TagsKind::Vi => {
             if dependency_tag_files.is_empty() {
                 if lib_tag_file != into_tag_file {
@@ -315,6 +732,10 @@ fn merge_tags(config: &Config,
         },
 
         TagsKind::Emacs => {
+            if config.inline_includes {
+                return etags::write_inlined(lib_tag_file, dependency_tag_files, into_tag_file);
+            }
+
             if lib_tag_file != into_tag_file {
                 copy_tags(config, lib_tag_file, into_tag_file)?;
             }
@@ -399,3 +820,40 @@ fn find_reexported_crates(src_dir: &Path) -> RtResult<Vec<CrateName>> {
 
     Ok(reexp_crates)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn vim_tags_list() {
+        let dir = TempDir::new().unwrap();
+        let tags_list = vim_tags_list_file(&dir.path().join("rusty-tags.vi"));
+        assert_eq!(tags_list, dir.path().join("rusty-tags.vi.vim"));
+
+        let tags_files = [Path::new("/proj/rusty-tags.vi"), Path::new("/cache/dep a,b|c.vi"), Path::new("C:\\cache\\std.vi")];
+        write_vim_tags_list("proj", &tags_files, &tags_list).unwrap();
+
+        let mut contents = String::new();
+        File::open(&tags_list).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "\" tags files of 'proj', created by rusty-tags\n\
+                              setlocal tags=/proj/rusty-tags.vi,/cache/dep\\ a\\\\,b\\|c.vi,C:\\\\cache\\\\std.vi\n");
+    }
+
+    #[test]
+    fn target_dirs_of_packages() {
+        let dir = TempDir::new().unwrap();
+        let pkg = dir.path().join("pkg");
+        fs::create_dir_all(pkg.join("src")).unwrap();
+        fs::create_dir_all(pkg.join("examples")).unwrap();
+        File::create(pkg.join("Cargo.toml")).unwrap();
+
+        assert_eq!(package_target_dirs(&[&pkg]), vec![pkg.join("target")]);
+
+        // the directories of the targets aren't packages
+        assert!(package_target_dirs(&[pkg.join("src"), pkg.join("examples")]).is_empty());
+        assert_eq!(package_target_dirs(&[pkg.join("src"), pkg.clone()]), vec![pkg.join("target")]);
+    }
+}
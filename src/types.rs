@@ -1,17 +1,27 @@
 use std::path::{Path, PathBuf};
-use std::fs::{self, File};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fmt;
+use std::thread;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
-use std::process::Command;
+use std::process::{self, Command};
 use std::ops::Deref;
 use std::cmp;
 use std::mem;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::Mutex;
 
 use semver::Version;
+use fs2::{self, FileExt};
 use rt_result::RtResult;
-use dirs::{rusty_tags_cache_dir, rusty_tags_locks_dir};
-use config::Config;
+use dirs::{rusty_tags_cache_dir, rusty_tags_locks_dir, mirrored_dir};
+use config::{self, Config};
+use cache::{CacheInfo, TagsKey};
+use probe::TagsCapabilities;
+use files::Excludes;
+use cleanup;
 
 /// The tree describing the dependencies of the whole cargo project.
 #[derive(Debug)]
@@ -31,7 +41,15 @@ pub struct DepTree {
     /// the parents - the dependent sources - of each
     /// source in 'sources', the source ids are indices into
     /// 'sources'
-    parents: Vec<Option<Vec<SourceId>>>
+    parents: Vec<Option<Vec<SourceId>>>,
+
+    /// all workspace members of the cargo project, 'roots'
+    /// might only contain a subset of them, the source ids
+    /// are indices into 'sources'
+    members: Vec<SourceId>,
+
+    /// the root directory of the cargo workspace
+    workspace_root: Option<PathBuf>
 }
 
 impl DepTree {
@@ -40,7 +58,9 @@ impl DepTree {
             roots: Vec::with_capacity(10),
             sources: Vec::new(),
             dependencies: Vec::new(),
-            parents: Vec::new()
+            parents: Vec::new(),
+            members: Vec::new(),
+            workspace_root: None
         }
     }
 
@@ -54,14 +74,16 @@ impl DepTree {
         Sources::new(&self.sources, Some(&self.roots))
     }
 
-    pub fn dependencies(&self, source: &Source) -> Sources {
-        Sources::new(&self.sources, self.dependencies_slice(source))
+    pub fn members(&self) -> Sources<'_> {
+        Sources::new(&self.sources, Some(&self.members))
     }
 
-    pub fn all_sources<'a>(&'a self) -> Box<dyn Iterator<Item=&Source> + 'a> {
-        Box::new(self.sources
-                     .iter()
-                     .filter_map(|s| s.as_ref()))
+    pub fn workspace_root(&self) -> Option<&Path> {
+        self.workspace_root.as_deref()
+    }
+
+    pub fn dependencies(&self, source: &Source) -> Sources {
+        Sources::new(&self.sources, self.dependencies_slice(source))
     }
 
     /// Get all of the ancestors of 'sources' till the roots.
@@ -81,6 +103,41 @@ impl DepTree {
         ancestor_srcs
     }
 
+    /// Get the roots and all of their direct and indirect dependencies.
+    pub fn reachable_sources(&self) -> Vec<&Source> {
+        let mut source_ids = HashSet::with_capacity(self.sources.len());
+        for id in &self.roots {
+            source_ids.insert(*id);
+            self.all_dependencies_internal(*id, &mut source_ids);
+        }
+
+        let mut srcs = Vec::with_capacity(source_ids.len());
+        for id in &source_ids {
+            if let Some(ref src) = self.sources[**id] {
+                srcs.push(src);
+            }
+        }
+
+        unique_sources(&mut srcs);
+        srcs
+    }
+
+    /// Get all of the direct and indirect dependencies of 'source'.
+    pub fn all_dependencies(&self, source: &Source) -> Vec<&Source> {
+        let mut dep_ids = HashSet::with_capacity(500);
+        self.all_dependencies_internal(source.id, &mut dep_ids);
+
+        let mut dep_srcs = Vec::with_capacity(dep_ids.len());
+        for id in &dep_ids {
+            if let Some(ref src) = self.sources[**id] {
+                dep_srcs.push(src);
+            }
+        }
+
+        unique_sources(&mut dep_srcs);
+        dep_srcs
+    }
+
     /// Reserve space for a new source and return its source id.
     pub fn new_source(&mut self) -> SourceId {
         let id = self.sources.len();
@@ -94,6 +151,14 @@ impl DepTree {
         self.roots = ids;
     }
 
+    pub fn set_members(&mut self, ids: Vec<SourceId>) {
+        self.members = ids;
+    }
+
+    pub fn set_workspace_root(&mut self, dir: PathBuf) {
+        self.workspace_root = Some(dir);
+    }
+
     pub fn set_source(&mut self, src: Source, dependencies: Vec<SourceId>) {
         let src_id = src.id;
         self.sources[*src_id] = Some(src);
@@ -149,6 +214,16 @@ impl DepTree {
         self.dependencies[*source.id].as_ref().map(Vec::as_slice)
     }
 
+    fn all_dependencies_internal(&self, source_id: SourceId, dependencies: &mut HashSet<SourceId>) {
+        if let Some(ref dep_ids) = self.dependencies[*source_id] {
+            for id in dep_ids {
+                if dependencies.insert(*id) {
+                    self.all_dependencies_internal(*id, dependencies);
+                }
+            }
+        }
+    }
+
     fn ancestors_internal<'a>(&'a self, source_id: SourceId,
                               ancestors: &mut HashSet<SourceId>) {
         if let Some(ref parent_ids) = self.parents[*source_id] {
@@ -216,38 +291,136 @@ impl<'a> Iterator for Sources<'a> {
 pub enum SourceLock {
     /// this running instance of 'rusty-tags' holds the lock
     Locked {
-        path: PathBuf
+        path: PathBuf,
+
+        /// the lock file with the OS level advisory lock, which is released
+        /// by the OS if this instance terminates without cleaning up
+        file: File
     },
 
-    /// an other instance of 'rusty-tags' holds the lock,
-    /// or the other instance couldn't cleanup the lock correctly
+    /// an other running instance of 'rusty-tags' holds the lock
     AlreadyLocked {
-        path: PathBuf
+        path: PathBuf,
+
+        /// the instance holding the lock, if it could be read from the lock file
+        holder: Option<LockHolder>
     }
 }
 
+/// The instance of 'rusty-tags' holding a lock, written into the lock file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+
+    /// seconds since the unix epoch when the lock was taken
+    pub started: u64
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        write!(f, "pid {} on host '{}', started {}s ago", self.pid, self.host, now.saturating_sub(self.started))
+    }
+}
+
+/// How long to wait for a lock held by an other running instance of 'rusty-tags'.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum LockWait {
+    NoWait,
+    Timeout(Duration),
+    Forever
+}
+
 impl SourceLock {
-    fn new(source: &Source, tags_spec: &TagsSpec) -> RtResult<SourceLock> {
-        let file_name = source.unique_file_name(tags_spec);
-        let lock_file = rusty_tags_locks_dir()?.join(file_name);
-        if lock_file.is_file() {
-            Ok(SourceLock::AlreadyLocked { path: lock_file })
-        } else {
-            let _ = File::create(&lock_file)?;
-            Ok(SourceLock::Locked {
-                path: lock_file
-            })
+    fn new(source: &Source, config: &Config) -> RtResult<SourceLock> {
+        let lock_file = rusty_tags_locks_dir()?.join(source.lock_file_name());
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lock_file)?;
+
+        let start = Instant::now();
+        let mut waiting = false;
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => break,
+
+                Err(ref err) if err.kind() == fs2::lock_contended_error().kind() => {
+                    let holder = read_lock_holder(&mut file);
+                    let timed_out = match config.lock_wait {
+                        LockWait::NoWait           => true,
+                        LockWait::Timeout(timeout) => start.elapsed() >= timeout,
+                        LockWait::Forever          => false
+                    };
+
+                    if timed_out {
+                        return Ok(SourceLock::AlreadyLocked { path: lock_file, holder });
+                    }
+
+                    if ! waiting {
+                        waiting = true;
+                        let holder_str = holder.map(|h| format!(" ({})", h)).unwrap_or_default();
+                        info!(config, "Waiting for the running rusty-tags{} creating the tags for '{}' ...",
+                              holder_str, source.name);
+                    }
+
+                    thread::sleep(Duration::from_millis(100));
+                }
+
+                Err(err) => return Err(err.into())
+            }
+        }
+
+        // the holder of a lock file with contents crashed or was killed
+        if let Some(holder) = read_lock_holder(&mut file) {
+            verbose!(config, "Reclaiming stale lock '{}' of {}", lock_file.display(), holder);
         }
+
+        let holder = LockHolder {
+            pid: process::id(),
+            host: hostname::get().map(|h| h.to_string_lossy().into_owned()).unwrap_or_default(),
+            started: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+        };
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        file.flush()?;
+
+        cleanup::register_lock_file(&lock_file);
+        Ok(SourceLock::Locked { path: lock_file, file })
+    }
+}
+
+fn read_lock_holder(file: &mut File) -> Option<LockHolder> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut contents).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// If the lock file isn't held by a running instance of 'rusty-tags',
+/// which terminated without cleaning up the lock file.
+pub fn is_stale_lock(lock_file: &Path) -> bool {
+    match OpenOptions::new().read(true).write(true).open(lock_file) {
+        Ok(file) => {
+            let is_stale = file.try_lock_exclusive().is_ok();
+            let _ = file.unlock();
+            is_stale
+        }
+
+        Err(_) => false
     }
 }
 
 impl Drop for SourceLock {
     fn drop(&mut self) {
         match *self {
-            SourceLock::Locked { ref path, .. } => {
-                if path.is_file() {
-                    let _ = fs::remove_file(&path);
-                }
+            // The lock file isn't removed, because an other instance might already
+            // have opened it and waits for the lock, after removing the file a further
+            // instance could lock a new file with the same path.
+            SourceLock::Locked { ref path, ref mut file } => {
+                let _ = file.set_len(0);
+                let _ = file.unlock();
+                cleanup::unregister_lock_file(path);
             }
 
             SourceLock::AlreadyLocked { .. } => {}
@@ -272,15 +445,58 @@ pub struct Source {
     /// hash of 'dir'
     pub hash: String,
 
+    /// where the source comes from
+    pub kind: SourceKind,
+
+    /// the already computed revision, see 'Source::revision'
+    revision: Mutex<Option<String>>,
+
     /// if the source is a root of the dependency tree,
     /// which means that it's a workspace member
     pub is_root: bool,
 
+    /// if the source is a procedural macro crate
+    pub is_proc_macro: bool,
+
+    /// if a crate root of the source enables the 'rustc_private'
+    /// feature, only detected for roots with 'Config::rustc_dev'
+    pub uses_rustc_private: bool,
+
+    /// the 'OUT_DIR' of the build script of the source
+    /// with the generated code, see 'Config::out_dir_tags'
+    pub out_dir: Option<PathBuf>,
+
+    /// the directories of the targets of the source beside of 'dir',
+    /// e.g. of examples, see 'Config::root_targets' and 'Config::dep_targets'
+    pub target_dirs: Vec<PathBuf>,
+
+    /// the languages - beside of Rust - tagged for the
+    /// source, see 'Config::extra_languages'
+    pub extra_languages: Vec<String>,
+
+    /// the patterns of the files excluded from the tags
+    /// of the source, see 'Config::exclude_paths'
+    pub exclude_paths: Vec<String>,
+
+    /// the enabled features of the source, only set if the
+    /// '#[cfg]' predicates are evaluated, see 'Config::target_cfg'
+    pub features: Option<Vec<String>>,
+
     /// the max depth of the source inside of the dependency tree,
     /// a source might be referenced multiple times and 'max_depth'
     /// contains the greatest depth of the source
     pub max_depth: Option<u32>,
 
+    /// the tags files of the source for each created kind of tags
+    pub tags_files: Vec<TagsFiles>
+}
+
+/// The tags files of a source for one kind of tags.
+#[derive(Debug)]
+pub struct TagsFiles {
+    /// the kind of the tags
+    pub kind: TagsKind,
+
     /// path to the tags file in the source directory,
     /// beside of the 'Cargo.toml' file, this tags file
     /// contains the tags of the source and of its
@@ -292,32 +508,247 @@ pub struct Source {
     /// the tags of the dependencies that have a public
     /// export from the source
     pub cached_tags_file: PathBuf,
+
+    /// the key of the configuration the cached tags are created with
+    pub key: TagsKey
+}
+
+impl TagsFiles {
+    /// Why the cached tags are outdated for `source`, by comparing their meta
+    /// data with the current configuration and the source, 'None' if they're
+    /// up to date.
+    fn outdated_reason(&self, source: &Source, config: &Config) -> Option<&'static str> {
+        let info = match CacheInfo::load(&self.cached_tags_file) {
+            Some(info) if info.key == self.key => info,
+            _ => return Some("the ctags executable, its options or the rusty-tags version changed")
+        };
+
+        if info.languages != source.extra_languages {
+            Some("its extra languages changed")
+        } else if info.src_dirs != source.src_dirs() {
+            Some("its tagged directories changed")
+        } else if info.exclude_paths != source.exclude_paths {
+            Some("its excluded paths changed")
+        } else if info.features != source.features {
+            Some("its enabled features changed")
+        } else {
+            match source.revision(config) {
+                Ok(ref revision) if *revision == info.revision => None,
+                _ => match source.kind {
+                    SourceKind::Git { .. } => Some("its git revision changed"),
+                    _                      => Some("its source files changed")
+                }
+            }
+        }
+    }
+}
+
+/// Where a source comes from, by the 'source' field of the package in the
+/// cargo metadata. The sources of registry packages never change, but
+/// path and git sources might change under the same name and version.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum SourceKind {
+    Registry,
+    Git { commit: String },
+    Path
+}
+
+impl SourceKind {
+    /// Creates the kind from the 'source' field of a package, which
+    /// is e.g. 'registry+https://...', 'git+https://...?branch=x#commit'
+    /// or null for path packages.
+    pub fn from_metadata(source: Option<&str>) -> SourceKind {
+        match source {
+            Some(src) if src.starts_with("git+") => {
+                let commit = src.rsplit('#').next().unwrap_or(src);
+                SourceKind::Git { commit: commit.to_string() }
+            }
+
+            Some(src) if src.starts_with("path+") => SourceKind::Path,
+            Some(_) => SourceKind::Registry,
+            None => SourceKind::Path
+        }
+    }
 }
 
 impl Source {
-    pub fn new(id: SourceId, name: &str, version: &Version, dir: &Path, is_root: bool, config: &Config) -> RtResult<Source> {
-        let tags_dir = find_dir_upwards_containing("Cargo.toml", dir).unwrap_or(dir.to_path_buf());
-        let tags_file = tags_dir.join(config.tags_spec.file_name());
-        let hash = source_hash(dir);
-        let cached_tags_file = {
-            let cache_dir = rusty_tags_cache_dir()?;
-            let file_name = format!("{}-{}.{}", name, hash, config.tags_spec.file_extension());
-            cache_dir.join(&file_name)
+    pub fn new(id: SourceId, name: &str, version: &Version, dir: &Path, kind: SourceKind, is_root: bool, config: &Config) -> RtResult<Source> {
+        let tags_dir = {
+            let src_tags_dir = find_dir_upwards_containing("Cargo.toml", dir).unwrap_or(dir.to_path_buf());
+            // with tags lists no tags files are written for dependencies,
+            // so there's no need to check if their source dir is writable
+            if (config.mirror_deps || config.tags_list) && ! is_root {
+                mirrored_dir(&src_tags_dir)?
+            } else if config.command != config::Command::UpdateTags {
+                // no tags are written, e.g. cleaning considers all possible tags files
+                src_tags_dir
+            } else {
+                // the tags of an unwritable source dir were written into the mirror
+                // dir by an earlier run, see 'tags::temp_tags_file'
+                let mirrored_tags_dir = mirrored_dir(&src_tags_dir)?;
+                let is_mirrored = config.tags_spec.kinds.iter().any(|k| {
+                    let file_name = config.tags_spec.file_name(*k);
+                    ! src_tags_dir.join(file_name).is_file() && mirrored_tags_dir.join(file_name).is_file()
+                });
+
+                if is_mirrored { mirrored_tags_dir } else { src_tags_dir }
+            }
         };
 
+        let hash = source_hash(dir);
+
+        let gitignore = respects_gitignore(config, &kind, is_root);
+        let cache_dir = rusty_tags_cache_dir()?;
+        let mut tags_files = Vec::with_capacity(config.tags_spec.kinds.len());
+        for kind in &config.tags_spec.kinds {
+            let key = config.tags_key(*kind, gitignore);
+            let file_name = format!("{}-{}-{}.{}", name, hash, key.id(), kind.file_extension());
+            let tags_file = match config.output {
+                // with workspace tags the output is used for them
+                Some(TagsOutput::File(ref path)) if is_root && ! config.workspace_tags => path.clone(),
+
+                // the tags are written to a temporary file, which
+                // is written to stdout after the tags creation
+                Some(TagsOutput::Stdout) if is_root && ! config.workspace_tags => {
+                    config.temp_file(&format!("{}.out", file_name))?
+                }
+
+                _ => tags_dir.join(config.tags_spec.file_name(*kind))
+            };
+
+            tags_files.push(TagsFiles {
+                kind: *kind,
+                tags_file,
+                cached_tags_file: cache_dir.join(&file_name),
+                key
+            });
+        }
+
         Ok(Source {
-            id: id,
+            id,
             max_depth: None,
             name: name.to_owned(),
             version: version.clone(),
             dir: dir.to_owned(),
-            hash: hash,
-            is_root: is_root,
-            tags_file: tags_file,
-            cached_tags_file: cached_tags_file
+            hash,
+            kind,
+            revision: Mutex::new(None),
+            is_root,
+            is_proc_macro: false,
+            uses_rustc_private: false,
+            out_dir: None,
+            target_dirs: Vec::new(),
+            extra_languages: config.extra_languages(name),
+            exclude_paths: config.exclude_paths(name),
+            features: None,
+            tags_files
         })
     }
 
+    /// Writes the tags of a workspace member at `workspace_root` into the member tags files,
+    /// because its tags files are the workspace tags, see 'TagsSpec::member_file_name'.
+    pub fn set_member_tags_files(&mut self, workspace_root: &Path, config: &Config) {
+        for tags_files in &mut self.tags_files {
+            if tags_files.tags_file == workspace_root.join(config.tags_spec.file_name(tags_files.kind)) {
+                tags_files.tags_file = workspace_root.join(config.tags_spec.member_file_name(tags_files.kind));
+            }
+        }
+    }
+
+    pub fn tags_files(&self, kind: TagsKind) -> &TagsFiles {
+        self.tags_files.iter()
+            .find(|f| f.kind == kind)
+            .expect("Expected tags files for every kind of the tags spec")
+    }
+
+    /// Sets the 'OUT_DIR' of the source, whose files are part of the revision
+    /// of the source, so that the tags are recreated if the generated code changes.
+    pub fn set_out_dir(&mut self, out_dir: PathBuf) {
+        self.out_dir = Some(out_dir);
+    }
+
+    /// Sets the directories of the targets of the source - e.g. of examples or of
+    /// binaries outside of 'dir' - without the ones nested in other directories.
+    /// The files of the target directories of path sources are part of the revision.
+    pub fn set_target_dirs(&mut self, target_dirs: Vec<PathBuf>) {
+        let mut dirs: Vec<PathBuf> = Vec::with_capacity(target_dirs.len());
+        for dir in target_dirs {
+            if ! dir.starts_with(&self.dir) && ! dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        let nested = |dir: &PathBuf| dirs.iter().any(|d| d != dir && dir.starts_with(d));
+        let mut dirs: Vec<PathBuf> = dirs.iter().filter(|d| ! nested(d)).cloned().collect();
+        dirs.sort();
+        self.target_dirs = dirs;
+    }
+
+    /// The revision of a mutable source - a git commit or a fingerprint of the files of a
+    /// path source and of its target directories, and of the files of the 'OUT_DIR' - used
+    /// to detect changes of the source since its tags were cached. 'None' for immutable
+    /// sources and for roots, whose tags are always recreated. The fingerprints are only
+    /// computed when they're needed the first time, e.g. never for cleaning.
+    pub fn revision(&self, config: &Config) -> RtResult<Option<String>> {
+        if self.is_root {
+            return Ok(None);
+        }
+
+        let mut revision = self.revision.lock().unwrap();
+        if revision.is_some() {
+            return Ok(revision.clone());
+        }
+
+        let tags_file_names = [config.tags_spec.file_name(TagsKind::Vi), config.tags_spec.file_name(TagsKind::Emacs)];
+        let mut revisions = Vec::new();
+        match self.kind {
+            SourceKind::Registry           => {}
+            SourceKind::Git { ref commit } => revisions.push(commit.clone()),
+            SourceKind::Path               => {
+                revisions.push(files_fingerprint(&self.dir, &tags_file_names, false)?);
+                for dir in &self.target_dirs {
+                    revisions.push(files_fingerprint(dir, &tags_file_names, false)?);
+                }
+            }
+        }
+
+        // build scripts might rewrite the same code on each run, so the
+        // generated files are only considered as changed by their contents
+        if let Some(ref out_dir) = self.out_dir {
+            revisions.push(files_fingerprint(out_dir, &[], true)?);
+        }
+
+        if ! revisions.is_empty() {
+            *revision = Some(revisions.join("+"));
+        }
+
+        Ok(revision.clone())
+    }
+
+    /// The files and directories excluded from the tags of the source, the
+    /// '.gitignore' files are only respected for roots and path sources.
+    pub fn excludes(&self, config: &Config) -> Excludes {
+        Excludes {
+            patterns: self.exclude_paths.clone(),
+            base_dir: find_dir_upwards_containing("Cargo.toml", &self.dir).ok(),
+            gitignore: respects_gitignore(config, &self.kind, self.is_root)
+        }
+    }
+
+    /// the directories the tags of the source are created for
+    pub fn src_dirs(&self) -> Vec<&Path> {
+        let mut dirs = Vec::with_capacity(self.target_dirs.len() + 2);
+
+        // a target directory might contain 'dir', e.g. a binary beside of the 'Cargo.toml'
+        if ! self.target_dirs.iter().any(|d| self.dir.starts_with(d)) {
+            dirs.push(self.dir.as_path());
+        }
+
+        dirs.extend(self.target_dirs.iter().map(PathBuf::as_path));
+        dirs.extend(self.out_dir.as_deref());
+        dirs
+    }
+
     pub fn needs_tags_update(&self, config: &Config) -> bool {
         if config.force_recreate {
             return true;
@@ -331,33 +762,54 @@ impl Source {
             return true;
         }
 
-        ! self.cached_tags_file.is_file() || ! self.tags_file.is_file()
+        self.tags_files.iter().any(|f| {
+            ! f.cached_tags_file.is_file()
+                || (! config.tags_list && ! f.tags_file.is_file())
+                || f.outdated_reason(self, config).is_some()
+        })
     }
 
     pub fn recreate_status(&self, config: &Config) -> String {
+        let missing_cache_file = self.tags_files.iter().find(|f| ! f.cached_tags_file.is_file());
+        let missing_tags_file = self.tags_files.iter().find(|f| ! config.tags_list && ! f.tags_file.is_file());
         if config.force_recreate {
             format!("Forced recreating of tags for {}", self.source_version())
         } else if self.is_root {
             format!("Recreating tags for cargo project root {}", self.source_version())
-        } else if ! self.cached_tags_file.is_file() {
+        } else if let Some(files) = missing_cache_file {
             format!("Recreating tags for {}, because of missing cache file at '{:?}'",
-                     self.source_version(), self.cached_tags_file)
-        } else if ! self.tags_file.is_file() {
+                     self.source_version(), files.cached_tags_file)
+        } else if let Some(files) = missing_tags_file {
             format!("Recreating tags for {}, because of missing tags file at '{:?}'",
-                     self.source_version(), self.tags_file)
+                     self.source_version(), files.tags_file)
+        } else if let Some(reason) = self.tags_files.iter().filter_map(|f| f.outdated_reason(self, config)).next() {
+            format!("Recreating tags for {}, because {}", self.source_version(), reason)
         } else {
             format!("Recreating tags for {}, because one of its dependencies was updated",
                     self.source_version())
         }
     }
 
-    pub fn lock(&self, tags_spec: &TagsSpec) -> RtResult<SourceLock> {
-        SourceLock::new(self, tags_spec)
+    /// All paths the tags file of `kind` might have been written to by rusty-tags,
+    /// beside of the 'Cargo.toml' of the source or inside of the mirror directory.
+    pub fn possible_tags_files(&self, config: &Config, kind: TagsKind) -> RtResult<Vec<PathBuf>> {
+        let src_tags_dir = find_dir_upwards_containing("Cargo.toml", &self.dir).unwrap_or(self.dir.clone());
+        let file_name = config.tags_spec.file_name(kind);
+        let mut tags_files = vec![self.tags_files(kind).tags_file.clone(),
+                                  src_tags_dir.join(file_name),
+                                  mirrored_dir(&src_tags_dir)?.join(file_name)];
+        tags_files.dedup();
+        Ok(tags_files)
+    }
+
+    pub fn lock(&self, config: &Config) -> RtResult<SourceLock> {
+        SourceLock::new(self, config)
     }
 
-    /// create a file name that's unique for each source
-    pub fn unique_file_name(&self, tags_spec: &TagsSpec) -> String {
-        format!("{}-{}.{}", self.name, self.hash, tags_spec.file_extension())
+    /// the name of the lock file of the source, which is independent of the kinds of tags,
+    /// so that instances creating overlapping kinds - e.g. 'vi' and 'vi,emacs' - exclude each other
+    pub fn lock_file_name(&self) -> String {
+        format!("{}-{}.lock", self.name, self.hash)
     }
 
     fn source_version(&self) -> String {
@@ -366,21 +818,33 @@ impl Source {
 }
 
 /// Temporary struct for the tags updating of the source. It's
-/// used to create and associate a temporary file to the source
+/// used to create and associate temporary files to the source
 /// for its tags creation.
 pub struct SourceWithTmpTags<'a> {
     /// the source to update
     pub source: &'a Source,
 
-    /// temporary file for the tags of the source
-    pub tags_file: PathBuf
+    /// temporary files for the tags of the source, one for
+    /// each created kind of tags
+    pub tags_files: Vec<(TagsKind, PathBuf)>
 }
 
 impl<'a> SourceWithTmpTags<'a> {
     pub fn new(config: &Config, source: &'a Source) -> RtResult<SourceWithTmpTags<'a>> {
-        let file_name = source.unique_file_name(&config.tags_spec);
-        let tags_file = config.temp_file(&file_name)?;
-        Ok(SourceWithTmpTags { source, tags_file })
+        let mut tags_files = Vec::with_capacity(config.tags_spec.kinds.len());
+        for kind in &config.tags_spec.kinds {
+            let file_name = format!("{}-{}.{}", source.name, source.hash, kind.file_extension());
+            tags_files.push((*kind, config.temp_file(&file_name)?));
+        }
+
+        Ok(SourceWithTmpTags { source, tags_files })
+    }
+
+    pub fn tags_file(&self, kind: TagsKind) -> &Path {
+        self.tags_files.iter()
+            .find(|&&(k, _)| k == kind)
+            .map(|(_, file)| file.as_path())
+            .expect("Expected a temporary tags file for every kind of the tags spec")
     }
 }
 
@@ -399,21 +863,121 @@ impl Deref for SourceId {
     }
 }
 
+/// if the '.gitignore' files are respected for a source of `kind`
+fn respects_gitignore(config: &Config, kind: &SourceKind, is_root: bool) -> bool {
+    config.gitignore && (is_root || *kind == SourceKind::Path)
+}
+
 fn source_hash(source_dir: &Path) -> String {
     let mut hasher = DefaultHasher::new();
     source_dir.hash(&mut hasher);
     hasher.finish().to_string()
 }
 
+/// A fingerprint of the paths, sizes and modification times of all files
+/// inside of `dir`, hidden entries, 'target' directories and the files
+/// named `ignored_files` - e.g. the tags files - are ignored. Symlinks
+/// aren't followed and unreadable entries are skipped. If `by_contents`
+/// is true, then the contents of the files are hashed instead of their
+/// sizes and modification times.
+fn files_fingerprint(dir: &Path, ignored_files: &[&str], by_contents: bool) -> RtResult<String> {
+    fn hash_dir(dir: &Path, ignored_files: &[&str], by_contents: bool, hasher: &mut DefaultHasher) {
+        let mut entries: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(Result::ok).collect(),
+            Err(_)      => return
+        };
+
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let file_name = entry.file_name();
+            if file_name.to_string_lossy().starts_with('.') || ignored_files.iter().any(|f| file_name == **f) {
+                continue;
+            }
+
+            let path = entry.path();
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_)       => continue
+            };
+
+            if metadata.is_dir() {
+                if file_name != "target" {
+                    hash_dir(&path, ignored_files, by_contents, hasher);
+                }
+            } else if by_contents {
+                path.hash(hasher);
+                fs::read(&path).unwrap_or_default().hash(hasher);
+            } else {
+                path.hash(hasher);
+                metadata.len().hash(hasher);
+                if let Ok(modified) = metadata.modified() {
+                    modified.hash(hasher);
+                }
+            }
+        }
+    }
+
+    // only the directory itself has to be readable
+    fs::read_dir(dir)?;
+    let mut hasher = DefaultHasher::new();
+    hash_dir(dir, ignored_files, by_contents, &mut hasher);
+    Ok(hasher.finish().to_string())
+}
+
 // which kind of tags are created
 arg_enum! {
-    #[derive(Eq, PartialEq, Debug)]
+    #[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
     pub enum TagsKind {
         Vi,
         Emacs
     }
 }
 
+impl TagsKind {
+    /// the extension used for caching the tags
+    pub fn file_extension(&self) -> &'static str {
+        match *self {
+            TagsKind::Vi    => "vi",
+            TagsKind::Emacs => "emacs"
+        }
+    }
+}
+
+/// where the tags of the cargo project are written to
+#[derive(Eq, PartialEq, Debug)]
+pub enum TagsOutput {
+    /// the tags are written to stdout
+    Stdout,
+
+    /// the tags are written to the file at the path
+    File(PathBuf)
+}
+
+/// The kinds of targets of a package - beside of its libraries and
+/// binaries, which are always tagged - whose directories are tagged.
+#[derive(Eq, PartialEq, Clone, Copy, Default, Debug)]
+pub struct ExtraTargets {
+    pub examples: bool,
+    pub benches: bool,
+    pub tests: bool
+}
+
+impl ExtraTargets {
+    pub fn all() -> ExtraTargets {
+        ExtraTargets { examples: true, benches: true, tests: true }
+    }
+
+    /// if the directories of targets of the cargo metadata `kind` are tagged
+    pub fn contains_kind(&self, kind: &str) -> bool {
+        match kind {
+            "example" => self.examples,
+            "bench"   => self.benches,
+            "test"    => self.tests,
+            _         => true
+        }
+    }
+}
+
 type ExeName = String;
 
 /// which ctags executable is used
@@ -426,9 +990,13 @@ pub enum TagsExe {
 /// holds additional info for the kind of tags, which extension
 /// they use for caching and which user viewable file names they get
 pub struct TagsSpec {
-    pub kind: TagsKind,
+    /// the kinds of tags that are created, each
+    /// kind is contained at most once
+    pub kinds: Vec<TagsKind>,
 
-    exe: TagsExe,
+    /// the ctags executable with its probed capabilities,
+    /// only known for the commands creating tags
+    ctags: Option<(TagsExe, TagsCapabilities)>,
 
     /// the file name for vi tags
     vi_tags: String,
@@ -437,71 +1005,172 @@ pub struct TagsSpec {
     emacs_tags: String,
 
     /// options given to the ctags executable
-    ctags_options: String
+    ctags_options: String,
+
+    /// if the tags of excluded code are removed, see 'CodeFilter'
+    pub filters_code: bool
 }
 
 impl TagsSpec {
-    pub fn new(kind: TagsKind, exe: TagsExe, vi_tags: String, emacs_tags: String, ctags_options: String) -> RtResult<TagsSpec> {
+    pub fn new(kinds: Vec<TagsKind>, ctags: Option<(TagsExe, TagsCapabilities)>, vi_tags: String, emacs_tags: String, ctags_options: String) -> RtResult<TagsSpec> {
         if vi_tags == emacs_tags {
             return Err(format!("It's not supported to use the same tags name '{}' for vi and emacs!", vi_tags).into());
         }
 
+        if kinds.is_empty() {
+            return Err("Expected at least one kind of tags!".into());
+        }
+
         Ok(TagsSpec {
-            kind: kind,
-            exe: exe,
-            vi_tags: vi_tags,
-            emacs_tags: emacs_tags,
-            ctags_options: ctags_options
+            kinds,
+            ctags,
+            vi_tags,
+            emacs_tags,
+            ctags_options,
+            filters_code: false
         })
     }
 
-    pub fn file_extension(&self) -> &'static str {
-        match self.kind {
-            TagsKind::Vi    => "vi",
-            TagsKind::Emacs => "emacs"
+    pub fn exe_name(&self) -> Option<&str> {
+        self.exe().ok().map(|(exe_name, _)| exe_name)
+    }
+
+    pub fn exe_version(&self) -> Option<&str> {
+        self.exe().ok().map(|(_, caps)| caps.version.as_str())
+    }
+
+    /// The name and the capabilities of the ctags executable, an error
+    /// for the commands not creating tags, which don't probe it.
+    fn exe(&self) -> RtResult<(&str, &TagsCapabilities)> {
+        match self.ctags {
+            Some((TagsExe::ExuberantCtags(ref exe_name), ref caps)) |
+            Some((TagsExe::UniversalCtags(ref exe_name), ref caps)) => Ok((exe_name, caps)),
+            None => Err("Expected a probed 'ctags' executable for the creation of tags!".into())
         }
     }
 
-    pub fn file_name(&self) -> &str {
-        match self.kind {
+    /// Checks if the ctags executable supports everything
+    /// needed for the creation of the tags.
+    pub fn check_exe_caps(&self) -> RtResult<()> {
+        let (exe_name, caps) = self.exe()?;
+        if ! caps.recurse {
+            return Err(format!("The 'ctags' executable '{}' ({}) doesn't support '--recurse', it's most likely too old. \
+                                Please install 'universal-ctags' or 'exuberant-ctags'!", exe_name, caps.version).into());
+        }
+
+        if self.kinds.contains(&TagsKind::Emacs) && ! caps.etags {
+            return Err(format!("The 'ctags' executable '{}' ({}) doesn't support emacs tags!", exe_name, caps.version).into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the name of `language` as known by the ctags executable, e.g. 'C++'
+    /// for 'c++', or an error if the ctags executable doesn't support it.
+    pub fn supported_language(&self, language: &str) -> RtResult<String> {
+        let (exe_name, caps) = self.exe()?;
+        match caps.languages.iter().find(|l| l.eq_ignore_ascii_case(language)) {
+            Some(supported) => Ok(supported.clone()),
+            None => Err(format!("The 'ctags' executable '{}' ({}) doesn't support the language '{}'! Supported languages: {}",
+                                exe_name, caps.version, language, caps.languages.join(", ")).into())
+        }
+    }
+
+    /// The file name patterns of Rust and the `extra_languages`,
+    /// 'None' if they aren't known for all of the languages.
+    pub fn file_patterns(&self, extra_languages: &[String]) -> Option<Vec<String>> {
+        let caps = self.exe().ok()?.1;
+        let language_patterns = |language: &str| {
+            caps.language_maps.iter()
+                .find(|(l, _)| l.eq_ignore_ascii_case(language))
+                .map(|(_, patterns)| patterns.clone())
+        };
+
+        // without a built-in parser the Rust files are mapped by '--langmap'
+        let mut patterns = if caps.rust_parser {
+            language_patterns("Rust")?
+        } else {
+            vec!["*.rs".to_string()]
+        };
+
+        for language in extra_languages {
+            patterns.extend(language_patterns(language)?);
+        }
+
+        Some(patterns)
+    }
+
+    pub fn ctags_options(&self) -> &str {
+        &self.ctags_options
+    }
+
+    pub fn file_name(&self, kind: TagsKind) -> &str {
+        match kind {
             TagsKind::Vi    => &self.vi_tags,
             TagsKind::Emacs => &self.emacs_tags
         }
     }
 
-    pub fn ctags_command(&self) -> Command {
-        match self.exe {
-            TagsExe::ExuberantCtags(ref exe_name) => {
-                let mut cmd = Command::new(&exe_name);
-                self.generic_ctags_options(&mut cmd);
-                cmd.arg("--languages=Rust")
-                   .arg("--langdef=Rust")
-                   .arg("--langmap=Rust:.rs")
-                   .arg("--regex-Rust=/^[ \\t]*(#\\[[^\\]]\\][ \\t]*)*(pub[ \\t]+)?(extern[ \\t]+)?(\"[^\"]+\"[ \\t]+)?(unsafe[ \\t]+)?(async[ \\t]+)?fn[ \\t]+([a-zA-Z0-9_]+)/\\7/f,functions,function definitions/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?type[ \\t]+([a-zA-Z0-9_]+)/\\2/T,types,type definitions/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?enum[ \\t]+([a-zA-Z0-9_]+)/\\2/g,enum,enumeration names/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?struct[ \\t]+([a-zA-Z0-9_]+)/\\2/s,structure names/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?mod[ \\t]+([a-zA-Z0-9_]+)\\s*\\{/\\2/m,modules,module names/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?(static|const)[ \\t]+([a-zA-Z0-9_]+)/\\3/c,consts,static constants/")
-                   .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?(unsafe[ \\t]+)?trait[ \\t]+([a-zA-Z0-9_]+)/\\3/t,traits,traits/")
-                   .arg("--regex-Rust=/^[ \\t]*macro_rules![ \\t]+([a-zA-Z0-9_]+)/\\1/d,macros,macro definitions/");
-
-                cmd
-            }
+    /// The file name of the tags of `kind` of a workspace member at the root of a workspace
+    /// with multiple members, so that they aren't overwritten by the workspace tags, e.g.
+    /// 'rusty-tags.member.vi' for 'rusty-tags.vi'.
+    pub fn member_file_name(&self, kind: TagsKind) -> String {
+        let file_name = self.file_name(kind);
+        match file_name.rfind('.') {
+            Some(dot) if dot > 0 => format!("{}.member{}", &file_name[..dot], &file_name[dot..]),
+            _                    => format!("{}.member", file_name)
+        }
+    }
 
-            TagsExe::UniversalCtags(ref exe_name) => {
-                let mut cmd = Command::new(&exe_name);
-                self.generic_ctags_options(&mut cmd);
-                cmd.arg("--languages=Rust");
+    /// If multiple kinds of tags are created, then ctags only creates
+    /// vi tags - containing the line numbers of the tags - and the
+    /// tags of the other kinds are converted from them. If ctags doesn't
+    /// support '--fields', then it's run for each kind of tags. The line
+    /// numbers are also needed for finding the tags of excluded code.
+    pub fn needs_line_numbers(&self) -> bool {
+        (self.kinds.len() > 1 || self.filters_code) && self.exe().map(|(_, caps)| caps.fields).unwrap_or(false)
+    }
 
-                cmd
-            }
+    /// The ctags command creating tags of `kind` for Rust and the `extra_languages`,
+    /// with `exclude_target` the directories named 'target' aren't tagged.
+    pub fn ctags_command(&self, kind: TagsKind, extra_languages: &[String], exclude_target: bool) -> RtResult<Command> {
+        let (exe_name, caps) = self.exe()?;
+        let mut cmd = Command::new(exe_name);
+        self.generic_ctags_options(kind, &mut cmd);
+        let mut languages = vec!["Rust"];
+        languages.extend(extra_languages.iter().map(String::as_str));
+        cmd.arg(format!("--languages={}", languages.join(",")));
+
+        if exclude_target {
+            cmd.arg("--exclude=target");
         }
+
+        // without a built-in parser - e.g. exuberant ctags - the
+        // Rust tags are defined by regular expressions
+        if ! caps.rust_parser {
+            cmd.arg("--langdef=Rust")
+               .arg("--langmap=Rust:.rs")
+               .arg("--regex-Rust=/^[ \\t]*(#\\[[^\\]]\\][ \\t]*)*(pub[ \\t]+)?(extern[ \\t]+)?(\"[^\"]+\"[ \\t]+)?(unsafe[ \\t]+)?(async[ \\t]+)?fn[ \\t]+([a-zA-Z0-9_]+)/\\7/f,functions,function definitions/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?type[ \\t]+([a-zA-Z0-9_]+)/\\2/T,types,type definitions/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?enum[ \\t]+([a-zA-Z0-9_]+)/\\2/g,enum,enumeration names/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?struct[ \\t]+([a-zA-Z0-9_]+)/\\2/s,structure names/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?mod[ \\t]+([a-zA-Z0-9_]+)\\s*\\{/\\2/m,modules,module names/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?(static|const)[ \\t]+([a-zA-Z0-9_]+)/\\3/c,consts,static constants/")
+               .arg("--regex-Rust=/^[ \\t]*(pub[ \\t]+)?(unsafe[ \\t]+)?trait[ \\t]+([a-zA-Z0-9_]+)/\\3/t,traits,traits/")
+               .arg("--regex-Rust=/^[ \\t]*macro_rules![ \\t]+([a-zA-Z0-9_]+)/\\1/d,macros,macro definitions/");
+        }
+
+        Ok(cmd)
     }
 
-    fn generic_ctags_options(&self, cmd: &mut Command) {
-        match self.kind {
-            TagsKind::Vi    => {}
+    fn generic_ctags_options(&self, kind: TagsKind, cmd: &mut Command) {
+        match kind {
+            TagsKind::Vi    => {
+                if self.needs_line_numbers() {
+                    cmd.arg("--fields=+n");
+                }
+            }
+
             TagsKind::Emacs => { cmd.arg("-e"); }
         }
 
@@ -538,3 +1207,47 @@ fn find_dir_upwards_containing(file_name: &str, start_dir: &Path) -> RtResult<Pa
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{File, OpenOptions};
+    use std::io::Write;
+    use tempfile::TempDir;
+    use super::*;
+
+    #[test]
+    fn lock_holders() {
+        let dir = TempDir::new().unwrap();
+        let lock_file = dir.path().join("proj-1234.lock");
+        let open = || OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lock_file).unwrap();
+
+        let mut file = open();
+        assert!(read_lock_holder(&mut file).is_none());
+
+        file.write_all(br#"{"pid":42,"host":"build-host","started":1000}"#).unwrap();
+        let holder = read_lock_holder(&mut file).unwrap();
+        assert_eq!((holder.pid, holder.host.as_str(), holder.started), (42, "build-host", 1000));
+        assert!(holder.to_string().starts_with("pid 42 on host 'build-host', started "));
+
+        // e.g. the record of an instance which crashed while writing it
+        file.set_len(0).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(br#"{"pid":42,"ho"#).unwrap();
+        assert!(read_lock_holder(&mut file).is_none());
+    }
+
+    #[test]
+    fn stale_locks() {
+        let dir = TempDir::new().unwrap();
+        let lock_file = dir.path().join("proj-1234.lock");
+        assert!(! is_stale_lock(&lock_file));
+
+        let file = File::create(&lock_file).unwrap();
+        assert!(is_stale_lock(&lock_file));
+
+        file.lock_exclusive().unwrap();
+        assert!(! is_stale_lock(&lock_file));
+        file.unlock().unwrap();
+        assert!(is_stale_lock(&lock_file));
+    }
+}